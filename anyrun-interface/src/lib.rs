@@ -3,7 +3,7 @@ use abi_stable::{
     library::RootModule,
     package_version_strings,
     sabi_types::VersionStrings,
-    std_types::{ROption, RString, RVec},
+    std_types::{ROption, RString, RVec, Tuple2},
     StableAbi,
 };
 
@@ -11,12 +11,34 @@ use abi_stable::{
 #[derive(StableAbi)]
 #[sabi(kind(Prefix(prefix_ref = PluginRef)))]
 #[sabi(missing_field(panic))]
+/// The fields after `handle_selection` were added later. Plugins built before they existed don't have them, so
+/// their accessors return `None`, which the host treats as the plugin not supporting the feature.
 pub struct Plugin {
     pub init: extern "C" fn(RString),
     pub info: extern "C" fn() -> PluginInfo,
     pub get_matches: extern "C" fn(RString) -> u64,
     pub poll_matches: extern "C" fn(u64) -> PollResult,
-    pub handle_selection: extern "C" fn(Match) -> HandleResult,
+    /// Called with the selected match and the id of the chosen `Action`, or `RNone` for the default action.
+    #[sabi(last_prefix_field)]
+    pub handle_selection: extern "C" fn(Match, ROption<u64>) -> HandleResult,
+    /// A file descriptor that becomes readable whenever `poll_matches` has something new to report.
+    /// Anyrun drains it before polling. A negative value means the plugin has to be polled on a timer.
+    #[sabi(missing_field(option))]
+    pub notify_fd: extern "C" fn() -> i32,
+    /// Tells the plugin that the results of the `get_matches` call with this id are not needed anymore.
+    #[sabi(missing_field(option))]
+    pub cancel_matches: extern "C" fn(u64),
+    /// Content for the preview pane, fetched when the match gets selected. `RNone` hides the pane.
+    #[sabi(missing_field(option))]
+    pub preview: extern "C" fn(Match) -> ROption<Preview>,
+    /// Handles all matches marked in multi-select mode at once, `RNone` if the plugin only handles them one by one.
+    /// Plugins providing it turn on multi-select mode when they are the only ones queried.
+    #[sabi(missing_field(option))]
+    pub handle_selections: ROption<extern "C" fn(RVec<Match>) -> HandleResult>,
+    /// Called with the query, returns the prompt to show in the entry when the query is meant for the plugin.
+    /// Also asked while the plugin is exclusive, where `RNone` shows the name of the plugin.
+    #[sabi(missing_field(option))]
+    pub prompt: extern "C" fn(RString) -> ROption<Prompt>,
 }
 
 /// Info of the plugin. Used for the main UI
@@ -32,8 +54,10 @@ pub struct PluginInfo {
 ///
 /// The `title` and `description` support pango markup when `use_pango` is set to true.
 /// Refer to [Pango Markup](https://docs.gtk.org/Pango/pango_markup.html) for how to use pango markup.
+///
+/// Optional fields are empty in `Match::default()`, so literals can end with `..Default::default()`.
 #[repr(C)]
-#[derive(StableAbi, Clone)]
+#[derive(StableAbi, Clone, Default)]
 pub struct Match {
     pub title: RString,
     pub description: ROption<RString>,
@@ -43,6 +67,49 @@ pub struct Match {
     pub icon: ROption<RString>,
     /// For runners to differentiate between the matches. Not required.
     pub id: ROption<u64>,
+    /// Alternatives to the default action, offered in the action menu of the match.
+    pub actions: RVec<Action>,
+    /// Opaque data for the plugin, handed back unchanged to `handle_selection`.
+    /// Useful to carry things like a path or a command line along with the match. Not required.
+    pub data: ROption<RString>,
+    /// Text that replaces the query when Tab is pressed with the match selected, to drill down step by step.
+    /// Not required.
+    pub completion: ROption<RString>,
+    /// CSS classes added to the row of the match, to style it apart from the rest, like `warning`.
+    pub style_classes: RVec<RString>,
+}
+
+/// A named action that can be chosen for a match instead of the default one
+#[repr(C)]
+#[derive(StableAbi, Clone, PartialEq)]
+pub struct Action {
+    /// Handed back to `handle_selection` when the action is chosen
+    pub id: u64,
+    pub title: RString,
+    /// The icon name from the icon theme in use
+    pub icon: ROption<RString>,
+}
+
+/// Content of the preview pane shown next to the list for the selected match
+#[repr(C)]
+#[derive(StableAbi)]
+pub enum Preview {
+    /// Text with [Pango Markup](https://docs.gtk.org/Pango/pango_markup.html)
+    Pango(RString),
+    /// Plain text shown with a monospace font
+    Monospace(RString),
+    /// Path to an image file
+    Image(RString),
+}
+
+/// Shown in the entry while the query goes to a single plugin
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct Prompt {
+    /// Shown in front of the entry next to the icon of the plugin, like dmenu's `-p`
+    pub text: RString,
+    /// Replaces the placeholder of the empty entry, `RNone` keeps the default one
+    pub placeholder: ROption<RString>,
 }
 
 /// For determining how anyrun should proceed after the plugin has handled a match selection
@@ -56,14 +123,65 @@ pub enum HandleResult {
     Refresh(bool),
     /// Copy the content, due to how copying works it must be done like this.
     Copy(RVec<u8>),
+    /// Copy the content with an explicit MIME type or to the primary selection.
+    CopyAs(CopyRequest),
     /// Output the content to stdout, printing to stdout has issues in plugins.
     Stdout(RVec<u8>),
+    /// Put new text in the entry instead of clearing it, like an expanded snippet or a directory to navigate into.
+    Query(QueryUpdate),
+    /// Launch a program detached from anyrun and close. Plugins should use this instead of spawning processes
+    /// themselves, so every program gets the same environment and process handling.
+    Spawn(SpawnRequest),
+    /// Show a short message to the user, like "Copied!", and clear the query.
+    Info(RString),
+    /// Show an error message to the user. The window stays open and the query is kept, so it can be fixed.
+    Error(RString),
+}
+
+/// Content for the clipboard, returned with `HandleResult::CopyAs`
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct CopyRequest {
+    pub bytes: RVec<u8>,
+    /// The MIME type of the content, like `image/png` or `text/uri-list`. `RNone` detects it from the content.
+    pub mime_type: ROption<RString>,
+    /// Copy to the primary selection instead of the regular clipboard
+    pub primary: bool,
+}
+
+/// A program to be launched by anyrun, returned with `HandleResult::Spawn`
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct SpawnRequest {
+    /// The program followed by its arguments
+    pub argv: RVec<RString>,
+    /// Environment variables to set on top of the ones anyrun has
+    pub env: RVec<Tuple2<RString, RString>>,
+    /// The working directory, `RNone` uses the home directory
+    pub cwd: ROption<RString>,
+    /// Run the program in the terminal set in the anyrun config
+    pub terminal: bool,
+}
+
+/// New content of the entry, set by `HandleResult::Query`
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct QueryUpdate {
+    pub text: RString,
+    /// Cursor position in characters, `RNone` puts it at the end of the text
+    pub cursor: ROption<u32>,
+    /// Sets an exclusive mode for the plugin, like `HandleResult::Refresh`
+    pub exclusive: bool,
 }
 
 #[repr(C)]
 #[derive(StableAbi)]
 pub enum PollResult {
+    /// The search is done, these are the last matches for the query.
     Ready(RVec<Match>),
+    /// The search is still running, but these matches were already found.
+    /// They are shown right away and more are to be expected.
+    Partial(RVec<Match>),
     Pending,
     Cancelled,
 }
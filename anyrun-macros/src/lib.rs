@@ -1,63 +1,138 @@
 use proc_macro::{Span, TokenStream};
+use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use syn::{parse_macro_input, parse_quote, Ident, ReturnType, Type};
 
-/// The function to handle the selection of an item. Takes a `Match` as its first argument, and the second argument can be one of:
-/// - &T
-/// - &mut T
+/// The function to handle the selection of an item. Takes a `Match` as its first argument, the following arguments can be:
+/// - &T or &mut T, where T is the type returned by `init`
+/// - `ROption<u64>`, the id of the chosen `Action`, or `RNone` for the default action
 /// - <Nothing>
-/// where T is the type returned by `init`.
 ///
-/// Should return a `HandleResult` with the appropriate action.
+/// Should return a `HandleResult` with the appropriate action. Before `init` is done, a `HandleResult::Error` is returned
+/// instead.
 #[proc_macro_attribute]
 pub fn handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let function = parse_macro_input!(item as syn::ItemFn);
     let fn_name = &function.sig.ident;
 
-    let data = if function.sig.inputs.len() == 2 {
-        if match function.sig.inputs.last() {
-            ::core::option::Option::Some(syn::FnArg::Typed(pat)) => match &*pat.ty {
-                Type::Reference(reference) => {
-                    reference.mutability.is_some()
-                }
-                _ => return quote! { compile_error!("Last argument must be either a reference to the shared data or should not be present at all.") }.into(),
-            },
-            ::core::option::Option::Some(_) => return quote! { compile_error!("`self` argument, really?") }.into(),
-            ::core::option::Option::None => unreachable!(),
-        } {
-            quote! {
-                ANYRUN_INTERNAL_DATA.write().unwrap().as_mut().unwrap(),
-            }
-        } else {
-            quote! {
-                ANYRUN_INTERNAL_DATA.read().unwrap().as_ref().unwrap(),
-            }
+    let mut data = None;
+    let mut args = Vec::new();
+
+    for input in function.sig.inputs.iter().skip(1) {
+        match shared_data_arg(
+            input,
+            &mut data,
+            |ty| is_action_type(ty).then(|| quote! { action }),
+            "Arguments after the selection must be a reference to the shared data or an `ROption<u64>`.",
+        ) {
+            Ok(arg) => args.push(arg),
+            Err(error) => return error,
         }
-    } else {
-        quote! {}
-    };
+    }
+
+    let fn_call = with_shared_data(
+        data,
+        true,
+        quote! { #fn_name(selection, #(#args),*) },
+        starting_up_error(),
+    );
 
     quote! {
         #[::abi_stable::sabi_extern_fn]
+        #[allow(unused_variables)]
         fn anyrun_internal_handle_selection(
             selection: ::anyrun_plugin::anyrun_interface::Match,
+            action: ::abi_stable::std_types::ROption<u64>,
         ) -> ::anyrun_plugin::anyrun_interface::HandleResult {
             #function
 
-            #fn_name(
-                selection,
-                #data
-            )
+            #fn_call
         }
     }
     .into()
 }
 
-/// Function that takes the current text input as an `RString` as the first argument, and the second argument can be one of:
-/// - &T
-/// - &mut T
+/// Whether the type is `ROption<u64>`, the id of the chosen action passed to the `handler`
+fn is_action_type(ty: &Type) -> bool {
+    let Type::Path(path) = ty else {
+        return false;
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return false;
+    };
+    let syn::PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return false;
+    };
+
+    segment.ident == "ROption"
+        && arguments.args.len() == 1
+        && matches!(
+            arguments.args.first(),
+            Some(syn::GenericArgument::Type(Type::Path(inner))) if inner.path.is_ident("u64")
+        )
+}
+
+/// Optional function to handle all matches marked in multi-select mode at once. Takes an `RVec<Match>` as its first
+/// argument, the following argument can be:
+/// - &T or &mut T, where T is the type returned by `init`
 /// - <Nothing>
-/// where T is the type returned by `init`.
+///
+/// Should return a `HandleResult` with the appropriate action. Plugins without this function get the `handler`
+/// called for every marked match, plugins with it turn on multi-select mode when they are the only ones queried.
+#[proc_macro_attribute]
+pub fn handle_selections(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as syn::ItemFn);
+    let fn_name = &function.sig.ident;
+
+    let mut data = None;
+    let mut args = Vec::new();
+
+    for input in function.sig.inputs.iter().skip(1) {
+        match shared_data_arg(
+            input,
+            &mut data,
+            |_| None,
+            "Argument after the selections must be a reference to the shared data.",
+        ) {
+            Ok(arg) => args.push(arg),
+            Err(error) => return error,
+        }
+    }
+
+    let fn_call = with_shared_data(
+        data,
+        true,
+        quote! { #fn_name(selections, #(#args),*) },
+        starting_up_error(),
+    );
+
+    quote! {
+        #[::abi_stable::sabi_extern_fn]
+        fn anyrun_internal_handle_selections(
+            selections: ::abi_stable::std_types::RVec<::anyrun_plugin::anyrun_interface::Match>,
+        ) -> ::anyrun_plugin::anyrun_interface::HandleResult {
+            #function
+
+            #fn_call
+        }
+
+        const ANYRUN_INTERNAL_HANDLE_SELECTIONS: ::abi_stable::std_types::ROption<
+            extern "C" fn(
+                ::abi_stable::std_types::RVec<::anyrun_plugin::anyrun_interface::Match>,
+            ) -> ::anyrun_plugin::anyrun_interface::HandleResult,
+        > = ::abi_stable::std_types::ROption::RSome(anyrun_internal_handle_selections);
+    }
+    .into()
+}
+
+/// Function that takes the current text input as an `RString` as the first argument, the following arguments can be:
+/// - &T or &mut T, where T is the type returned by `init`
+/// - `MatchSender`, to hand out matches before the search is done
+/// - `CancellationToken`, to stop early once the input has changed
+/// - <Nothing>
+///
+/// Only one search runs at a time, a new input cancels the running one and is handled as soon as it
+/// returns.
 ///
 /// It should return an `RVec` of `Match`es.
 #[proc_macro_attribute]
@@ -65,36 +140,56 @@ pub fn get_matches(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let function = parse_macro_input!(item as syn::ItemFn);
     let fn_name = &function.sig.ident;
 
-    let fn_call = if function.sig.inputs.len() == 2 {
-        let data = if match function.sig.inputs.last() {
-            ::core::option::Option::Some(syn::FnArg::Typed(pat)) => match &*pat.ty {
-                Type::Reference(reference) => {
-                    reference.mutability.is_some()
-                }
-                _ => return quote! { compile_error!("Last argument must be either a reference to the shared data or should not be present at all.") }.into(),
+    let mut data = None;
+    let mut uses_sender = false;
+    let mut uses_token = false;
+    let mut args = Vec::new();
+
+    for input in function.sig.inputs.iter().skip(1) {
+        match shared_data_arg(
+            input,
+            &mut data,
+            |ty| match ty {
+                Type::Path(path) => match path.path.segments.last() {
+                    Some(segment) if segment.ident == "MatchSender" => {
+                        uses_sender = true;
+                        Some(quote! { sender })
+                    }
+                    Some(segment) if segment.ident == "CancellationToken" => {
+                        uses_token = true;
+                        Some(quote! { token })
+                    }
+                    _ => None,
+                },
+                _ => None,
             },
-            ::core::option::Option::Some(_) => return quote! { compile_error!("`self` argument, really?") }.into(),
-            ::core::option::Option::None => unreachable!(),
-        } {
-            quote! {
-                ANYRUN_INTERNAL_DATA.write().unwrap().as_mut()
-            }
-        } else {
-            quote! {
-                ANYRUN_INTERNAL_DATA.read().unwrap().as_ref()
-            }
-        };
+            "Arguments after the input must be either a reference to the shared data, a `MatchSender` or a `CancellationToken`.",
+        ) {
+            Ok(arg) => args.push(arg),
+            Err(error) => return error,
+        }
+    }
+
+    let fn_call = with_shared_data(
+        data,
+        true,
+        quote! { #fn_name(input, #(#args),*) },
+        quote! { ::abi_stable::std_types::RVec::new() },
+    );
+
+    let sender = if uses_sender {
         quote! {
-            if let ::core::option::Option::Some(data) = #data {
-                #fn_name(input, data)
-            } else {
-                ::abi_stable::std_types::RVec::new()
-            }
+            let sender = ::anyrun_plugin::MatchSender::new(task.clone());
         }
     } else {
+        quote! {}
+    };
+    let token = if uses_token {
         quote! {
-            #fn_name(input)
+            let token = ::anyrun_plugin::CancellationToken::new(task.clone());
         }
+    } else {
+        quote! {}
     };
 
     quote! {
@@ -102,23 +197,191 @@ pub fn get_matches(_attr: TokenStream, item: TokenStream) -> TokenStream {
         fn anyrun_internal_get_matches(input: ::abi_stable::std_types::RString) -> u64 {
             #function
 
-            let current_id =
-                ANYRUN_INTERNAL_ID_COUNTER.load(::std::sync::atomic::Ordering::Relaxed);
-            ANYRUN_INTERNAL_ID_COUNTER
-                .store(current_id + 1, ::std::sync::atomic::Ordering::Relaxed);
-
-            let handle = ::std::thread::spawn(move || {
+            #[allow(unused_variables)]
+            fn anyrun_internal_search(
+                input: ::abi_stable::std_types::RString,
+                task: &::std::sync::Arc<::anyrun_plugin::Task>,
+            ) -> ::abi_stable::std_types::RVec<::anyrun_plugin::anyrun_interface::Match> {
+                #sender
+                #token
                 #fn_call
-            });
+            }
+
+            ANYRUN_INTERNAL_WORKER.submit(input, anyrun_internal_search)
+        }
+    }
+    .into()
+}
+
+/// Optional function providing the content of the preview pane. Takes the selected `Match` as its first argument, the
+/// following argument can be:
+/// - &T or &mut T, where T is the type returned by `init`
+/// - <Nothing>
+///
+/// Should return an `ROption<Preview>`, `RNone` hides the pane. Plugins without this function never show a preview.
+///
+/// It is called on the UI thread, so it never waits for the shared data. While a search holds it mutably, or before
+/// `init` is done, the pane is hidden instead.
+#[proc_macro_attribute]
+pub fn preview(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as syn::ItemFn);
+    let fn_name = &function.sig.ident;
+
+    let mut data = None;
+    let mut args = Vec::new();
+
+    for input in function.sig.inputs.iter().skip(1) {
+        match shared_data_arg(
+            input,
+            &mut data,
+            |_| None,
+            "Argument after the selection must be a reference to the shared data.",
+        ) {
+            Ok(arg) => args.push(arg),
+            Err(error) => return error,
+        }
+    }
+
+    let fn_call = with_shared_data(
+        data,
+        false,
+        quote! { #fn_name(selection, #(#args),*) },
+        quote! { ::abi_stable::std_types::ROption::RNone },
+    );
 
-            *ANYRUN_INTERNAL_THREAD.lock().unwrap() = ::core::option::Option::Some((handle, current_id));
+    quote! {
+        #[::abi_stable::sabi_extern_fn]
+        fn anyrun_internal_preview(
+            selection: ::anyrun_plugin::anyrun_interface::Match,
+        ) -> ::abi_stable::std_types::ROption<::anyrun_plugin::anyrun_interface::Preview> {
+            #function
 
-            current_id
+            #fn_call
         }
     }
     .into()
 }
 
+/// Optional function providing the prompt shown in the entry. Takes the query as an `RString` as its first argument,
+/// the following argument can be:
+/// - &T or &mut T, where T is the type returned by `init`
+/// - <Nothing>
+///
+/// Should return an `ROption<Prompt>`, `RSome` when the query is meant for the plugin, like when it starts with the
+/// prefix the plugin reacts to. It is also asked while the plugin is the only one being queried.
+///
+/// It is called on the UI thread for every change of the query, so it never waits for the shared data. While a search
+/// holds it mutably, or before `init` is done, no prompt is shown.
+#[proc_macro_attribute]
+pub fn prompt(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as syn::ItemFn);
+    let fn_name = &function.sig.ident;
+
+    let mut data = None;
+    let mut args = Vec::new();
+
+    for input in function.sig.inputs.iter().skip(1) {
+        match shared_data_arg(
+            input,
+            &mut data,
+            |_| None,
+            "Argument after the query must be a reference to the shared data.",
+        ) {
+            Ok(arg) => args.push(arg),
+            Err(error) => return error,
+        }
+    }
+
+    let fn_call = with_shared_data(
+        data,
+        false,
+        quote! { #fn_name(input, #(#args),*) },
+        quote! { ::abi_stable::std_types::ROption::RNone },
+    );
+
+    quote! {
+        #[::abi_stable::sabi_extern_fn]
+        fn anyrun_internal_prompt(
+            input: ::abi_stable::std_types::RString,
+        ) -> ::abi_stable::std_types::ROption<::anyrun_plugin::anyrun_interface::Prompt> {
+            #function
+
+            #fn_call
+        }
+    }
+    .into()
+}
+
+/// Checks an argument after the first one of a plugin function and returns what to pass for it. A reference to the
+/// shared data is passed as `data`, with `data` set to whether it is mutable. Any other type is handed to `other`,
+/// which returns `None` if the function can't take it, so the compile error `unexpected` is raised instead.
+fn shared_data_arg(
+    input: &syn::FnArg,
+    data: &mut Option<bool>,
+    other: impl FnOnce(&Type) -> Option<TokenStream2>,
+    unexpected: &str,
+) -> Result<TokenStream2, TokenStream> {
+    let syn::FnArg::Typed(pat) = input else {
+        return Err(quote! { compile_error!("`self` argument, really?"); }.into());
+    };
+
+    match &*pat.ty {
+        // Both would be borrowed from the same lock, `&mut T` next to `&T` never gets it
+        Type::Reference(_) if data.is_some() => Err(quote! {
+            compile_error!("The shared data can only be taken once, use a single `&T` or `&mut T`.");
+        }
+        .into()),
+        Type::Reference(reference) => {
+            *data = Some(reference.mutability.is_some());
+            Ok(quote! { data })
+        }
+        ty => other(ty).ok_or_else(|| quote! { compile_error!(#unexpected); }.into()),
+    }
+}
+
+/// The result of a handler called before `init` is done
+fn starting_up_error() -> TokenStream2 {
+    quote! {
+        ::anyrun_plugin::anyrun_interface::HandleResult::Error(
+            ::abi_stable::std_types::RString::from("The plugin is still starting up"),
+        )
+    }
+}
+
+/// Calls the function of the plugin with `data` bound to the shared data, if it takes it, and returns `fallback` while
+/// the data is not initialized yet. The functions anyrun calls on its UI thread must not `wait` for a search that
+/// borrows the data mutably, so they also return `fallback` while the data is locked.
+fn with_shared_data(
+    data: Option<bool>,
+    wait: bool,
+    call: TokenStream2,
+    fallback: TokenStream2,
+) -> TokenStream2 {
+    let (write, read) = if wait {
+        (quote! { write }, quote! { read })
+    } else {
+        (quote! { try_write }, quote! { try_read })
+    };
+
+    match data {
+        Some(true) => quote! {
+            let mut guard = ANYRUN_INTERNAL_DATA.#write().ok();
+            match guard.as_deref_mut().and_then(::core::option::Option::as_mut) {
+                ::core::option::Option::Some(data) => #call,
+                ::core::option::Option::None => #fallback,
+            }
+        },
+        Some(false) => quote! {
+            let guard = ANYRUN_INTERNAL_DATA.#read().ok();
+            match guard.as_deref().and_then(::core::option::Option::as_ref) {
+                ::core::option::Option::Some(data) => #call,
+                ::core::option::Option::None => #fallback,
+            }
+        },
+        None => call,
+    }
+}
+
 /// Function that returns the plugin info as a `PluginInfo` object. Takes no arguments.
 #[proc_macro_attribute]
 pub fn info(_attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -137,7 +400,8 @@ pub fn info(_attr: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 /// Function that takes an `RString` as the only argument, which points to the anyrun config directory. Returns the data
-/// the plugin operates on. This data is accessible as both a normal borrow and a mutable borrow to `get_matches` and `handler`.
+/// the plugin operates on. This data is accessible as both a normal borrow and a mutable borrow to `get_matches` and `handler`,
+/// each function takes it at most once.
 #[proc_macro_attribute]
 pub fn init(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let function = parse_macro_input!(item as syn::ItemFn);
@@ -148,16 +412,11 @@ pub fn init(_attr: TokenStream, item: TokenStream) -> TokenStream {
     };
 
     quote! {
-        static ANYRUN_INTERNAL_THREAD: ::std::sync::Mutex<
-            Option<(
-                ::std::thread::JoinHandle<
-                    ::abi_stable::std_types::RVec<::anyrun_plugin::anyrun_interface::Match>,
-                >,
-                u64,
-            )>,
-        > = ::std::sync::Mutex::new(None);
-        static ANYRUN_INTERNAL_ID_COUNTER: ::std::sync::atomic::AtomicU64 =
-            ::std::sync::atomic::AtomicU64::new(0);
+        // Defaults for the optional functions, shadowed by the ones the plugin defines
+        #[allow(unused_imports)]
+        use ::anyrun_plugin::defaults::*;
+
+        static ANYRUN_INTERNAL_WORKER: ::anyrun_plugin::Worker = ::anyrun_plugin::Worker::new();
         static ANYRUN_INTERNAL_DATA: ::std::sync::RwLock<Option<#data_type>> =
             ::std::sync::RwLock::new(None);
 
@@ -170,30 +429,28 @@ pub fn init(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 get_matches: anyrun_internal_get_matches,
                 poll_matches: anyrun_internal_poll_matches,
                 handle_selection: anyrun_internal_handle_selection,
+                notify_fd: anyrun_internal_notify_fd,
+                cancel_matches: anyrun_internal_cancel_matches,
+                preview: anyrun_internal_preview,
+                prompt: anyrun_internal_prompt,
+                handle_selections: ANYRUN_INTERNAL_HANDLE_SELECTIONS,
             }
             .leak_into_prefix()
         }
 
+        #[::abi_stable::sabi_extern_fn]
+        fn anyrun_internal_notify_fd() -> i32 {
+            ::anyrun_plugin::notify_fd()
+        }
+
         #[::abi_stable::sabi_extern_fn]
         fn anyrun_internal_poll_matches(id: u64) -> ::anyrun_plugin::anyrun_interface::PollResult {
-            match ANYRUN_INTERNAL_THREAD.try_lock() {
-                ::core::result::Result::Ok(thread) => match thread.as_ref() {
-                    ::core::option::Option::Some((thread, task_id)) => {
-                        if *task_id == id {
-                            if !thread.is_finished() {
-                                return ::anyrun_plugin::anyrun_interface::PollResult::Pending;
-                            }
-                        } else {
-                            return ::anyrun_plugin::anyrun_interface::PollResult::Cancelled;
-                        }
-                    }
-                    ::core::option::Option::None => return ::anyrun_plugin::anyrun_interface::PollResult::Cancelled,
-                },
-                ::core::result::Result::Err(_) => return ::anyrun_plugin::anyrun_interface::PollResult::Pending,
-            }
+            ANYRUN_INTERNAL_WORKER.poll(id)
+        }
 
-            let (thread, _) = ANYRUN_INTERNAL_THREAD.lock().unwrap().take().unwrap();
-            ::anyrun_plugin::anyrun_interface::PollResult::Ready(thread.join().unwrap())
+        #[::abi_stable::sabi_extern_fn]
+        fn anyrun_internal_cancel_matches(id: u64) {
+            ANYRUN_INTERNAL_WORKER.cancel(id)
         }
 
         #[::abi_stable::sabi_extern_fn]
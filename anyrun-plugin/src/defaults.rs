@@ -0,0 +1,20 @@
+//! Implementations of the optional plugin functions for plugins that don't define them.
+//!
+//! These are glob imported by `init`, so the functions generated by the attribute macros take
+//! precedence over them.
+
+use abi_stable::std_types::{ROption, RString, RVec};
+use anyrun_interface::{HandleResult, Match, Preview, Prompt};
+
+#[abi_stable::sabi_extern_fn]
+pub fn anyrun_internal_preview(_selection: Match) -> ROption<Preview> {
+    ROption::RNone
+}
+
+#[abi_stable::sabi_extern_fn]
+pub fn anyrun_internal_prompt(_input: RString) -> ROption<Prompt> {
+    ROption::RNone
+}
+
+pub const ANYRUN_INTERNAL_HANDLE_SELECTIONS: ROption<extern "C" fn(RVec<Match>) -> HandleResult> =
+    ROption::RNone;
@@ -1,12 +1,23 @@
 /*!
 The crate for building plugins for Anyrun.
 
-Each plugin needs 4 functions defined, `init`, `info`, `get_matches` and the `handler`. Documentation
-on what each of these should be is found in their respective attribute macros.
+Each plugin needs 4 functions defined, `init`, `info`, `get_matches` and the `handler`. Optional functions like
+`preview` can be added on top. Documentation on what each of these should be is found in their respective attribute
+macros.
 !*/
 
-pub use anyrun_interface::{self, HandleResult, Match, PluginInfo};
-pub use anyrun_macros::{get_matches, handler, info, init};
+#[doc(hidden)]
+pub mod defaults;
+mod task;
+
+pub use anyrun_interface::{
+    self, Action, CopyRequest, HandleResult, Match, PluginInfo, Preview, Prompt, QueryUpdate,
+    SpawnRequest,
+};
+pub use anyrun_macros::{get_matches, handle_selections, handler, info, init, preview, prompt};
+#[doc(hidden)]
+pub use task::{notify_fd, Search, Task, Worker};
+pub use task::{CancellationToken, MatchSender};
 
 /*
 The macro to create a plugin, handles asynchronous execution of getting the matches and the boilerplate
@@ -0,0 +1,437 @@
+use std::{
+    io::Write,
+    os::unix::{io::AsRawFd, net::UnixStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    thread,
+};
+
+use abi_stable::std_types::{RString, RVec};
+use anyrun_interface::{Match, PollResult};
+
+/// Socket pair used to wake up anyrun, the reading end is handed out with `notify_fd`
+static NOTIFIER: OnceLock<Option<(UnixStream, UnixStream)>> = OnceLock::new();
+
+fn notifier() -> Option<&'static (UnixStream, UnixStream)> {
+    NOTIFIER
+        .get_or_init(|| {
+            let (reader, writer) = UnixStream::pair().ok()?;
+            reader.set_nonblocking(true).ok()?;
+            writer.set_nonblocking(true).ok()?;
+            Some((reader, writer))
+        })
+        .as_ref()
+}
+
+/// The file descriptor that becomes readable when there is something new to poll, or -1 if it
+/// could not be created.
+#[doc(hidden)]
+pub fn notify_fd() -> i32 {
+    notifier().map_or(-1, |(reader, _)| reader.as_raw_fd())
+}
+
+fn notify() {
+    if let Some((_, writer)) = notifier() {
+        // A full buffer means anyrun has not read the previous notifications yet, so it will be
+        // woken up anyway.
+        let _ = (&*writer).write(&[0]);
+    }
+}
+
+/// State of a single `get_matches` call shared with the polling side
+#[doc(hidden)]
+#[derive(Default)]
+pub struct Task {
+    batch: Mutex<Vec<Match>>,
+    result: Mutex<Option<RVec<Match>>>,
+    finished: AtomicBool,
+    cancelled: AtomicBool,
+}
+
+impl Task {
+    fn finish(&self, matches: RVec<Match>) {
+        *self.result.lock().unwrap() = Some(matches);
+        self.finished.store(true, Ordering::Release);
+        notify();
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Hands out the matches found so far, the second value tells whether the search is done.
+    fn poll(&self) -> (RVec<Match>, bool) {
+        let finished = self.finished.load(Ordering::Acquire);
+        let mut matches: RVec<Match> = std::mem::take(&mut *self.batch.lock().unwrap()).into();
+
+        if finished {
+            if let Some(result) = self.result.lock().unwrap().take() {
+                matches.extend(result);
+            }
+        }
+
+        (matches, finished)
+    }
+}
+
+/// Lets a `get_matches` function hand out matches before the whole search is done.
+///
+/// Take it as an argument of the `get_matches` function to receive one. Matches sent with it are
+/// shown right away, before the ones returned by the function.
+#[derive(Clone)]
+pub struct MatchSender {
+    task: Arc<Task>,
+}
+
+impl MatchSender {
+    #[doc(hidden)]
+    pub fn new(task: Arc<Task>) -> Self {
+        Self { task }
+    }
+
+    /// Queues the matches to be picked up by anyrun.
+    pub fn send(&self, matches: impl IntoIterator<Item = Match>) {
+        self.task.batch.lock().unwrap().extend(matches);
+        notify();
+    }
+}
+
+/// Tells a `get_matches` function that its results are not needed anymore, because the input
+/// changed in the meantime.
+///
+/// Take it as an argument of the `get_matches` function to receive one. Long searches should check
+/// it every now and then and return early once it is cancelled, whatever they return is discarded.
+#[derive(Clone)]
+pub struct CancellationToken {
+    task: Arc<Task>,
+}
+
+impl CancellationToken {
+    #[doc(hidden)]
+    pub fn new(task: Arc<Task>) -> Self {
+        Self { task }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.task.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Function running the search of a plugin for the given input
+#[doc(hidden)]
+pub type Search = fn(RString, &Arc<Task>) -> RVec<Match>;
+
+struct WorkerState {
+    next_id: u64,
+    /// The latest request, waiting for the worker to pick it up
+    queued: Option<(u64, RString, Arc<Task>)>,
+    /// The request the worker is running or has finished last
+    current: Option<(u64, Arc<Task>)>,
+    started: bool,
+}
+
+/// Runs the searches of a plugin on a single thread, always picking the latest request.
+///
+/// Requests that arrive while a search is running cancel it, and requests that get superseded
+/// before the worker gets to them are dropped without running at all.
+#[doc(hidden)]
+pub struct Worker {
+    state: Mutex<WorkerState>,
+    wakeup: Condvar,
+}
+
+impl Worker {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(WorkerState {
+                next_id: 0,
+                queued: None,
+                current: None,
+                started: false,
+            }),
+            wakeup: Condvar::new(),
+        }
+    }
+
+    /// Queues a search for `input` and returns the id to poll it with. `search` must be the same
+    /// function for every call.
+    pub fn submit(&'static self, input: RString, search: Search) -> u64 {
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.next_id;
+        state.next_id += 1;
+
+        if let Some((_, task)) = &state.current {
+            task.cancel();
+        }
+        state.queued = Some((id, input, Arc::new(Task::default())));
+
+        if !state.started {
+            state.started = true;
+            thread::spawn(move || self.run(search));
+        }
+        self.wakeup.notify_one();
+
+        id
+    }
+
+    fn run(&self, search: Search) {
+        loop {
+            let (input, task) = {
+                let mut state = self.state.lock().unwrap();
+                let (id, input, task) = loop {
+                    match state.queued.take() {
+                        Some(request) => break request,
+                        None => state = self.wakeup.wait(state).unwrap(),
+                    }
+                };
+                state.current = Some((id, task.clone()));
+                (input, task)
+            };
+
+            let matches =
+                panic::catch_unwind(AssertUnwindSafe(|| search(input, &task))).unwrap_or_default();
+            task.finish(matches);
+        }
+    }
+
+    pub fn poll(&self, id: u64) -> PollResult {
+        let mut state = self.state.lock().unwrap();
+
+        if matches!(&state.queued, Some((queued_id, _, _)) if *queued_id == id) {
+            return PollResult::Pending;
+        }
+
+        let (matches, finished) = match &state.current {
+            Some((current_id, task)) if *current_id == id => task.poll(),
+            _ => return PollResult::Cancelled,
+        };
+
+        if finished {
+            state.current = None;
+            PollResult::Ready(matches)
+        } else if matches.is_empty() {
+            PollResult::Pending
+        } else {
+            PollResult::Partial(matches)
+        }
+    }
+
+    pub fn cancel(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+
+        if matches!(&state.queued, Some((queued_id, _, _)) if *queued_id == id) {
+            state.queued = None;
+        }
+        if let Some((current_id, task)) = &state.current {
+            if *current_id == id {
+                task.cancel();
+            }
+        }
+    }
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::AtomicU32, time::Duration};
+
+    use super::*;
+
+    fn rmatch(title: &str) -> Match {
+        Match {
+            title: title.into(),
+            ..Match::default()
+        }
+    }
+
+    fn titles(matches: &RVec<Match>) -> Vec<&str> {
+        matches.iter().map(|rmatch| rmatch.title.as_str()).collect()
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        for _ in 0..5000 {
+            if condition() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("Timed out waiting for the worker");
+    }
+
+    /// Polls until the worker has something to say about the request
+    fn wait_for(worker: &Worker, id: u64) -> PollResult {
+        for _ in 0..5000 {
+            match worker.poll(id) {
+                PollResult::Pending => thread::sleep(Duration::from_millis(1)),
+                result => return result,
+            }
+        }
+        panic!("Timed out waiting for the worker");
+    }
+
+    fn until_cancelled(task: &Arc<Task>) {
+        let token = CancellationToken::new(task.clone());
+        wait_until(|| token.is_cancelled());
+    }
+
+    #[test]
+    fn superseded_requests_are_dropped() {
+        static WORKER: Worker = Worker::new();
+        static STARTED: AtomicBool = AtomicBool::new(false);
+        static RELEASED: AtomicBool = AtomicBool::new(false);
+        static SEEN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        fn search(input: RString, _: &Arc<Task>) -> RVec<Match> {
+            SEEN.lock().unwrap().push(input.to_string());
+            STARTED.store(true, Ordering::SeqCst);
+            wait_until(|| RELEASED.load(Ordering::SeqCst));
+            vec![rmatch(&input)].into()
+        }
+
+        let slow = WORKER.submit("slow".into(), search);
+        wait_until(|| STARTED.load(Ordering::SeqCst));
+        let skipped = WORKER.submit("skipped".into(), search);
+        let latest = WORKER.submit("latest".into(), search);
+        RELEASED.store(true, Ordering::SeqCst);
+
+        assert!(matches!(WORKER.poll(skipped), PollResult::Cancelled));
+        let PollResult::Ready(matches) = wait_for(&WORKER, latest) else {
+            panic!("The latest request did not finish");
+        };
+        assert_eq!(titles(&matches), ["latest"]);
+        assert!(matches!(WORKER.poll(slow), PollResult::Cancelled));
+        assert_eq!(*SEEN.lock().unwrap(), ["slow", "latest"]);
+    }
+
+    #[test]
+    fn poll_follows_the_search() {
+        static WORKER: Worker = Worker::new();
+        static STEP: AtomicU32 = AtomicU32::new(0);
+
+        fn search(_: RString, task: &Arc<Task>) -> RVec<Match> {
+            wait_until(|| STEP.load(Ordering::SeqCst) >= 1);
+            MatchSender::new(task.clone()).send([rmatch("early")]);
+            wait_until(|| STEP.load(Ordering::SeqCst) >= 2);
+            vec![rmatch("late")].into()
+        }
+
+        let id = WORKER.submit("query".into(), search);
+        assert!(matches!(WORKER.poll(id), PollResult::Pending));
+
+        STEP.store(1, Ordering::SeqCst);
+        let PollResult::Partial(matches) = wait_for(&WORKER, id) else {
+            panic!("The sent matches were not handed out");
+        };
+        assert_eq!(titles(&matches), ["early"]);
+        assert!(matches!(WORKER.poll(id), PollResult::Pending));
+
+        STEP.store(2, Ordering::SeqCst);
+        let PollResult::Ready(matches) = wait_for(&WORKER, id) else {
+            panic!("The search did not finish");
+        };
+        assert_eq!(titles(&matches), ["late"]);
+        // Finished requests are forgotten once their results are handed out
+        assert!(matches!(WORKER.poll(id), PollResult::Cancelled));
+    }
+
+    #[test]
+    fn cancel_sets_the_token() {
+        static WORKER: Worker = Worker::new();
+        static STARTED: AtomicBool = AtomicBool::new(false);
+        static NOTICED: AtomicBool = AtomicBool::new(false);
+
+        fn search(_: RString, task: &Arc<Task>) -> RVec<Match> {
+            STARTED.store(true, Ordering::SeqCst);
+            until_cancelled(task);
+            NOTICED.store(true, Ordering::SeqCst);
+            RVec::new()
+        }
+
+        let id = WORKER.submit("query".into(), search);
+        wait_until(|| STARTED.load(Ordering::SeqCst));
+        WORKER.cancel(id);
+
+        wait_until(|| NOTICED.load(Ordering::SeqCst));
+        assert!(matches!(wait_for(&WORKER, id), PollResult::Ready(_)));
+    }
+
+    #[test]
+    fn cancelled_queued_requests_never_run() {
+        static WORKER: Worker = Worker::new();
+        static STARTED: AtomicBool = AtomicBool::new(false);
+        static RELEASED: AtomicBool = AtomicBool::new(false);
+        static RUNS: AtomicU32 = AtomicU32::new(0);
+
+        fn search(_: RString, _: &Arc<Task>) -> RVec<Match> {
+            RUNS.fetch_add(1, Ordering::SeqCst);
+            STARTED.store(true, Ordering::SeqCst);
+            wait_until(|| RELEASED.load(Ordering::SeqCst));
+            RVec::new()
+        }
+
+        let running = WORKER.submit("running".into(), search);
+        wait_until(|| STARTED.load(Ordering::SeqCst));
+        let queued = WORKER.submit("queued".into(), search);
+        WORKER.cancel(queued);
+        RELEASED.store(true, Ordering::SeqCst);
+
+        assert!(matches!(WORKER.poll(queued), PollResult::Cancelled));
+        assert!(matches!(wait_for(&WORKER, running), PollResult::Ready(_)));
+        assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn sent_matches_are_batched_until_polled() {
+        static WORKER: Worker = Worker::new();
+        static STEP: AtomicU32 = AtomicU32::new(0);
+
+        fn search(_: RString, task: &Arc<Task>) -> RVec<Match> {
+            let sender = MatchSender::new(task.clone());
+            sender.send([rmatch("first")]);
+            sender.send([rmatch("second"), rmatch("third")]);
+            STEP.store(1, Ordering::SeqCst);
+            wait_until(|| STEP.load(Ordering::SeqCst) >= 2);
+            sender.send([rmatch("fourth")]);
+            vec![rmatch("returned")].into()
+        }
+
+        let id = WORKER.submit("query".into(), search);
+        wait_until(|| STEP.load(Ordering::SeqCst) >= 1);
+
+        let PollResult::Partial(matches) = WORKER.poll(id) else {
+            panic!("The sent matches were not handed out");
+        };
+        assert_eq!(titles(&matches), ["first", "second", "third"]);
+
+        // Matches sent right before returning come ahead of the returned ones
+        STEP.store(2, Ordering::SeqCst);
+        let PollResult::Ready(matches) = wait_for(&WORKER, id) else {
+            panic!("The search did not finish");
+        };
+        assert_eq!(titles(&matches), ["fourth", "returned"]);
+    }
+
+    #[test]
+    fn panicking_search_returns_nothing() {
+        static WORKER: Worker = Worker::new();
+
+        fn search(_: RString, _: &Arc<Task>) -> RVec<Match> {
+            panic!("Search failed");
+        }
+
+        let id = WORKER.submit("query".into(), search);
+        let PollResult::Ready(matches) = wait_for(&WORKER, id) else {
+            panic!("The failed search did not finish");
+        };
+        assert!(matches.is_empty());
+    }
+}
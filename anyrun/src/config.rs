@@ -1,10 +1,21 @@
 use anyrun_interface::PluginRef as Plugin;
 use clap::{Parser, ValueEnum};
-use gtk::{gdk::Rectangle, gio, glib};
+use gtk::{
+    gdk::Rectangle,
+    gio,
+    glib::{self, SourceId},
+};
 use serde::Deserialize;
 use std::{
     fs,
     path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    history::History,
+    plugins::Refresh,
+    view::{Footer, MatchView, PromptChip},
 };
 
 // Config struct and its implementation
@@ -26,6 +37,9 @@ pub struct Config {
     #[serde(default = "Config::default_plugins")]
     pub plugins: Vec<PathBuf>,
 
+    #[serde(default)]
+    pub plugin_priority: Vec<PathBuf>,
+
     #[serde(default)]
     pub hide_match_icons: bool,
     #[serde(default)]
@@ -33,6 +47,16 @@ pub struct Config {
     #[serde(default)]
     pub hide_plugin_info: bool,
     #[serde(default)]
+    pub hide_match_descriptions: bool,
+    #[serde(default = "Config::default_icon_size")]
+    pub match_icon_size: i32,
+    #[serde(default = "Config::default_icon_size")]
+    pub plugin_icon_size: i32,
+    #[serde(default = "Config::default_row_height")]
+    pub row_height: i32,
+    #[serde(default = "Config::default_plugin_info_width")]
+    pub plugin_info_width: i32,
+    #[serde(default)]
     pub steal_focus: bool,
     #[serde(default)]
     pub ignore_exclusive_zones: bool,
@@ -45,6 +69,10 @@ pub struct Config {
     #[serde(default)]
     pub layer: Layer,
     #[serde(default)]
+    pub monitor: MonitorChoice,
+    #[serde(default)]
+    pub theme: Theme,
+    #[serde(default)]
     pub bottom_entry: bool,
 
     #[serde(default)]
@@ -52,6 +80,40 @@ pub struct Config {
 
     #[serde(default)]
     pub daemon: bool,
+
+    #[serde(default = "Config::default_history_size")]
+    pub history_size: usize,
+
+    #[serde(default)]
+    pub show_preview: bool,
+    #[serde(default = "Config::default_preview_width")]
+    pub preview_width: RelativeNum,
+
+    #[serde(default)]
+    pub multi_select: bool,
+
+    #[serde(default)]
+    pub show_footer: bool,
+
+    #[serde(default = "Config::default_terminal")]
+    pub terminal: Vec<String>,
+    #[serde(default)]
+    pub systemd_scope: bool,
+
+    #[serde(default)]
+    pub copy_timeout: u64,
+
+    #[serde(default)]
+    pub keybinds: Vec<Keybind>,
+    #[serde(default)]
+    pub quick_select_badges: bool,
+
+    #[serde(default)]
+    pub grid_plugins: Vec<PathBuf>,
+    #[serde(default = "Config::default_grid_columns")]
+    pub grid_columns: u32,
+    #[serde(default = "Config::default_grid_icon_size")]
+    pub grid_icon_size: i32,
 }
 
 impl Config {
@@ -75,6 +137,45 @@ impl Config {
             "libtranslate.so".into(),
         ]
     }
+
+    fn default_icon_size() -> i32 {
+        32
+    }
+
+    fn default_row_height() -> i32 {
+        36
+    }
+
+    fn default_plugin_info_width() -> i32 {
+        200
+    }
+
+    fn default_history_size() -> usize {
+        500
+    }
+
+    fn default_preview_width() -> RelativeNum {
+        RelativeNum::Fraction(0.5)
+    }
+
+    fn default_terminal() -> Vec<String> {
+        vec!["xterm".into(), "-e".into()]
+    }
+
+    fn default_grid_columns() -> u32 {
+        6
+    }
+
+    fn default_grid_icon_size() -> i32 {
+        64
+    }
+
+    /// The matches are shown in a grid when all plugins taking part in the query are grid plugins
+    pub fn use_grid(&self, plugin_ids: impl IntoIterator<Item = usize>) -> bool {
+        let mut plugin_ids = plugin_ids.into_iter().peekable();
+        plugin_ids.peek().is_some()
+            && plugin_ids.all(|plugin_id| self.grid_plugins.contains(&self.plugins[plugin_id]))
+    }
 }
 
 impl Default for Config {
@@ -85,17 +186,38 @@ impl Default for Config {
             edges: Self::default_edges(),
             margin: Vec::default(),
             plugins: Self::default_plugins(),
+            plugin_priority: Vec::default(),
             hide_match_icons: false,
             hide_plugins_icons: true,
             hide_plugin_info: false,
+            hide_match_descriptions: false,
+            match_icon_size: Self::default_icon_size(),
+            plugin_icon_size: Self::default_icon_size(),
+            row_height: Self::default_row_height(),
+            plugin_info_width: Self::default_plugin_info_width(),
             ignore_exclusive_zones: false,
             steal_focus: false,
             show_results_immediately: false,
             layer: Layer::default(),
+            monitor: MonitorChoice::default(),
+            theme: Theme::default(),
             bottom_entry: false,
             save_entry_state: false,
             smooth_input_time: 0,
             daemon: false,
+            history_size: Self::default_history_size(),
+            show_preview: false,
+            preview_width: Self::default_preview_width(),
+            multi_select: false,
+            show_footer: false,
+            terminal: Self::default_terminal(),
+            systemd_scope: false,
+            copy_timeout: 0,
+            keybinds: Vec::default(),
+            quick_select_badges: false,
+            grid_plugins: Vec::default(),
+            grid_columns: Self::default_grid_columns(),
+            grid_icon_size: Self::default_grid_icon_size(),
         }
     }
 }
@@ -145,6 +267,46 @@ impl Default for Layer {
     }
 }
 
+/// Stylesheet shipped with anyrun, the `style.css` of the user is applied on top of it
+#[derive(Deserialize, Clone, Copy, ValueEnum, Default)]
+pub enum Theme {
+    /// Only the GTK theme in use
+    #[default]
+    System,
+    Light,
+    Dark,
+    HighContrast,
+}
+
+/// The monitor to show the window on
+#[derive(Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(from = "String")]
+pub enum MonitorChoice {
+    /// The one the compositor puts the window on, usually the focused one
+    #[default]
+    Focused,
+    /// The one with the most pixels
+    Largest,
+    /// A connector name like `DP-1` or `eDP-1`
+    Connector(String),
+}
+
+impl From<&str> for MonitorChoice {
+    fn from(value: &str) -> Self {
+        match value {
+            "focused" => Self::Focused,
+            "largest" => Self::Largest,
+            connector => Self::Connector(connector.to_string()),
+        }
+    }
+}
+
+impl From<String> for MonitorChoice {
+    fn from(value: String) -> Self {
+        value.as_str().into()
+    }
+}
+
 // RelativeNum enum and its implementation
 #[derive(Deserialize, Clone, Copy)]
 pub enum RelativeNum {
@@ -179,6 +341,81 @@ impl From<&str> for RelativeNum {
     }
 }
 
+#[derive(Deserialize, Clone)]
+pub struct Keybind {
+    /// Key combo like `ctrl+j` or `alt+Return`, keys are named like in GDK
+    pub key: String,
+    pub action: KeyAction,
+}
+
+impl From<&str> for Keybind {
+    fn from(value: &str) -> Self {
+        let (key, action) = value
+            .rsplit_once('=')
+            .expect("Invalid keybind, expected <key>=<action>");
+
+        Self {
+            key: key.to_string(),
+            action: action.into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum KeyAction {
+    /// Select the next match
+    Next,
+    /// Select the previous match
+    Prev,
+    PageDown,
+    PageUp,
+    /// Select the first match
+    First,
+    /// Select the last match
+    Last,
+    /// Activate the selected match
+    Activate,
+    /// Open the action menu of the selected match
+    SecondaryAction,
+    /// Complete the query from the selected match, or open its action menu when it has no completion
+    Complete,
+    /// Mark the selected match in multi-select mode
+    ToggleMark,
+    ClearQuery,
+    Close,
+    /// Query only the plugin of the selected match, or all plugins again
+    TogglePlugin,
+    /// Activate the nth match from the top of the visible part of the list, counting from 1
+    ActivateNth(u32),
+}
+
+impl From<&str> for KeyAction {
+    fn from(value: &str) -> Self {
+        match value {
+            "next" => Self::Next,
+            "prev" => Self::Prev,
+            "page-down" => Self::PageDown,
+            "page-up" => Self::PageUp,
+            "first" => Self::First,
+            "last" => Self::Last,
+            "activate" => Self::Activate,
+            "secondary-action" => Self::SecondaryAction,
+            "complete" => Self::Complete,
+            "toggle-mark" => Self::ToggleMark,
+            "clear-query" => Self::ClearQuery,
+            "close" => Self::Close,
+            "toggle-plugin" => Self::TogglePlugin,
+            other => match other
+                .strip_prefix("activate-nth:")
+                .and_then(|n| n.parse().ok())
+            {
+                Some(n) => Self::ActivateNth(n),
+                None => panic!("Invalid keybind action"),
+            },
+        }
+    }
+}
+
 // Args struct for command line arguments
 #[derive(Parser)]
 pub struct Args {
@@ -199,25 +436,59 @@ pub enum Command {
     Show,
     Hide,
     Close,
+    /// Forget which matches were selected for which queries
+    ClearHistory,
 }
 
 // Enum for actions after GTK has finished
 pub enum PostRunAction {
-    Copy(Vec<u8>),
+    Copy(CopyContent),
     None,
 }
 
+pub struct CopyContent {
+    pub bytes: Vec<u8>,
+    /// `None` detects the MIME type from the content
+    pub mime_type: Option<String>,
+    pub primary: bool,
+}
+
 // Struct for runtime data
 pub struct RuntimeData {
     pub exclusive: Option<Plugin>,
+    /// Plugin the search was limited to with the `TogglePlugin` keybind, unlike `exclusive` it is kept while typing
+    pub pinned: Option<Plugin>,
     pub plugins: Vec<Plugin>,
+    /// Position of every plugin's group in the list, indexed the same way as `plugins`
+    pub plugin_ranks: Vec<usize>,
     pub post_run_action: PostRunAction,
     pub config: Config,
     pub error_label: String,
+    /// Shows messages from plugins, set once the window is built
+    pub banner: Option<gtk::Label>,
+    /// Shows the errors in `style.css` until it loads cleanly, set once the window is built
+    pub css_errors: Option<gtk::Label>,
+    /// The main window, set once it is built
+    pub window: Option<gtk::ApplicationWindow>,
+    /// The list or grid of matches, set once the window is built
+    pub view: Option<Rc<MatchView>>,
+    /// Shows which plugin the query goes to, set once the window is built
+    pub prompt: Option<PromptChip>,
+    /// The status line under the list, set once the window is built if enabled
+    pub footer: Option<Footer>,
+    /// The preview pane, set once it is built if enabled
+    pub preview: Option<gtk::ScrolledWindow>,
+    /// Holds the `style.css` of the user, reloaded in place when the file changes
+    pub css_provider: Option<gtk::CssProvider>,
+    pub css_monitor: Option<gio::FileMonitor>,
+    /// Hides an informational message from the banner again
+    pub banner_timeout: Option<SourceId>,
     pub config_dir: PathBuf,
     pub geometry: Rectangle,
     pub list_store: gio::ListStore,
     pub app_state: gio::Settings,
+    pub history: History,
+    pub refresh: Refresh,
 }
 
 /// The naming scheme for CSS styling
@@ -231,6 +502,26 @@ pub mod style_names {
     pub const MATCH: &str = "match";
     pub const MATCH_TITLE: &str = "match-title";
     pub const MATCH_DESC: &str = "match-desc";
+    pub const ACTIONS: &str = "actions";
+    pub const ACTION_TITLE: &str = "action-title";
+    pub const PREVIEW: &str = "preview";
+    /// CSS class of the rows marked in multi-select mode
+    pub const MARKED: &str = "marked";
+    pub const BANNER: &str = "banner";
+    /// The errors in the `style.css` of the user
+    pub const CSS_ERRORS: &str = "css-errors";
+    /// CSS class of the first row of each plugin's group
+    pub const FIRST: &str = "first";
+    /// Prefix of the CSS class every row gets from the name of its plugin
+    pub const PLUGIN_PREFIX: &str = "plugin-";
+    /// The numbers next to the matches that can be activated with the quick-select keybinds
+    pub const QUICK_INDEX: &str = "quick-index";
+    /// The status line under the list
+    pub const FOOTER: &str = "footer";
+    /// The plugin icon and prompt in front of the entry
+    pub const PROMPT: &str = "prompt";
+    /// CSS class of the window while a single plugin is being queried
+    pub const EXCLUSIVE: &str = "exclusive";
 }
 
 pub const APP_ID: &str = "com.kirottu.anyrun";
@@ -2,10 +2,10 @@ use abi_stable::{
     std_types::{ROption, RString},
     traits::IntoReprRust,
 };
-use anyrun_interface::Match as RMatch;
+use anyrun_interface::{Action, Match as RMatch};
 use gtk::{
     gio::prelude::*,
-    glib::{self, subclass::prelude::*, ParamSpec},
+    glib::{self, clone, subclass::prelude::*, ParamSpec},
     prelude::*,
 };
 use std::{
@@ -32,6 +32,14 @@ mod imp {
         id_some: Cell<bool>,
         pub plugin_id: Cell<u64>,
         pub first: Cell<bool>,
+        pub data: RefCell<Option<String>>,
+        pub completion: RefCell<Option<String>>,
+        pub marked: Cell<bool>,
+        /// Position among the rows at the top of the list for the quick-select keybinds, 0 for none
+        pub quick_index: Cell<u32>,
+        // not a property, glib has no type for a list of plugin structs
+        pub actions: RefCell<Vec<Action>>,
+        pub style_classes: RefCell<Vec<String>>,
     }
 
     #[glib::object_subclass]
@@ -55,6 +63,10 @@ mod imp {
                     glib::ParamSpecBoolean::builder("id-some").build(),
                     glib::ParamSpecUInt64::builder("plugin-id").build(),
                     glib::ParamSpecBoolean::builder("first").build(),
+                    glib::ParamSpecString::builder("data").build(),
+                    glib::ParamSpecString::builder("completion").build(),
+                    glib::ParamSpecBoolean::builder("marked").build(),
+                    glib::ParamSpecUInt::builder("quick-index").build(),
                 ]
             })
         }
@@ -109,6 +121,30 @@ mod imp {
                         .expect("type conformity checked by `Object::set_property`");
                     self.first.replace(first);
                 }
+                "data" => {
+                    let data = value
+                        .get()
+                        .expect("type conformity checked by `Object::set_property`");
+                    self.data.replace(data);
+                }
+                "completion" => {
+                    let completion = value
+                        .get()
+                        .expect("type conformity checked by `Object::set_property`");
+                    self.completion.replace(completion);
+                }
+                "marked" => {
+                    let marked = value
+                        .get()
+                        .expect("type conformity checked by `Object::set_property`");
+                    self.marked.replace(marked);
+                }
+                "quick-index" => {
+                    let quick_index = value
+                        .get()
+                        .expect("type conformity checked by `Object::set_property`");
+                    self.quick_index.replace(quick_index);
+                }
                 _ => unimplemented!(),
             }
         }
@@ -123,6 +159,10 @@ mod imp {
                 "id-some" => self.id_some.get().to_value(),
                 "plugin-id" => self.plugin_id.get().to_value(),
                 "first" => self.first.get().to_value(),
+                "data" => self.data.borrow().to_value(),
+                "completion" => self.completion.borrow().to_value(),
+                "marked" => self.marked.get().to_value(),
+                "quick-index" => self.quick_index.get().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -210,6 +250,69 @@ impl GMatch {
         self.set_property("first", value);
     }
 
+    pub fn get_data(&self) -> Option<String> {
+        self.property("data")
+    }
+
+    pub fn set_data(&self, value: Option<String>) {
+        self.set_property("data", value)
+    }
+
+    pub fn get_completion(&self) -> Option<String> {
+        self.property("completion")
+    }
+
+    pub fn set_completion(&self, value: Option<String>) {
+        self.set_property("completion", value)
+    }
+
+    pub fn get_marked(&self) -> bool {
+        self.property("marked")
+    }
+
+    pub fn set_marked(&self, value: bool) {
+        self.set_property("marked", value)
+    }
+
+    pub fn get_quick_index(&self) -> u32 {
+        self.property("quick-index")
+    }
+
+    pub fn set_quick_index(&self, value: u32) {
+        self.set_property("quick-index", value)
+    }
+
+    pub fn get_actions(&self) -> Vec<Action> {
+        self.imp().actions.borrow().clone()
+    }
+
+    pub fn set_actions(&self, value: Vec<Action>) {
+        self.imp().actions.replace(value);
+    }
+
+    pub fn get_style_classes(&self) -> Vec<String> {
+        self.imp().style_classes.borrow().clone()
+    }
+
+    pub fn set_style_classes(&self, value: Vec<String>) {
+        self.imp().style_classes.replace(value);
+    }
+
+    /// Whether both matches would be shown the same way, used to keep unchanged rows on refresh
+    pub fn same_as(&self, other: &GMatch) -> bool {
+        self.get_title() == other.get_title()
+            && self.get_description() == other.get_description()
+            && self.get_use_pango() == other.get_use_pango()
+            && self.get_icon() == other.get_icon()
+            && self.get_id() == other.get_id()
+            && self.get_plugin_id() == other.get_plugin_id()
+            && self.get_first() == other.get_first()
+            && self.get_actions() == other.get_actions()
+            && self.get_data() == other.get_data()
+            && self.get_completion() == other.get_completion()
+            && self.get_style_classes() == other.get_style_classes()
+    }
+
     pub fn to_widget(&self, runtime_data: Rc<RefCell<RuntimeData>>) -> gtk::Widget {
         let runtime_data = runtime_data.borrow();
         let plugin = runtime_data
@@ -219,20 +322,20 @@ impl GMatch {
 
         let hbox = gtk::Box::builder()
             .orientation(gtk::Orientation::Horizontal)
-            .height_request(36)
+            .height_request(runtime_data.config.row_height)
             .spacing(4)
             .build();
 
         let plugin_info_box = gtk::Box::builder()
             .orientation(gtk::Orientation::Horizontal)
             // .halign(gtk::Align::Center)
-            .width_request(200)
+            .width_request(runtime_data.config.plugin_info_width)
             .spacing(12)
             .build();
 
         let plugin_info = plugin.info()();
 
-        let plugin_icon = build_image(&plugin_info.icon);
+        let plugin_icon = build_image(&plugin_info.icon, runtime_data.config.plugin_icon_size);
         plugin_icon.set_margin_start(4);
         plugin_icon.set_margin_end(8);
         plugin_info_box.append(&plugin_icon);
@@ -260,7 +363,7 @@ impl GMatch {
 
         if !runtime_data.config.hide_match_icons {
             if let Some(icon) = self.get_icon() {
-                match_box.append(&build_image(&icon));
+                match_box.append(&build_image(&icon, runtime_data.config.match_icon_size));
             }
         }
 
@@ -276,7 +379,10 @@ impl GMatch {
             &self.get_title(),
         ));
 
-        if let Some(desc) = self.get_description() {
+        if let Some(desc) = self
+            .get_description()
+            .filter(|_| !runtime_data.config.hide_match_descriptions)
+        {
             vbox.append(&build_label(
                 style_names::MATCH_DESC,
                 self.get_use_pango(),
@@ -287,8 +393,134 @@ impl GMatch {
         match_box.append(&vbox);
         hbox.append(&match_box);
 
+        if runtime_data.config.quick_select_badges {
+            let badge = self.build_badge();
+            badge.set_valign(gtk::Align::Center);
+            badge.set_margin_end(4);
+            hbox.append(&badge);
+        }
+
+        self.add_style_classes(&hbox, &plugin_info.name);
+
         hbox.into()
     }
+
+    /// Widget of the match for the grid layout, a large icon with the title under it
+    pub fn to_grid_widget(&self, runtime_data: Rc<RefCell<RuntimeData>>) -> gtk::Widget {
+        let runtime_data = runtime_data.borrow();
+        let plugin = runtime_data
+            .plugins
+            .get(self.get_plugin_id() as usize)
+            .expect("Can't get plugin by id");
+
+        let vbox = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(4)
+            .build();
+
+        let plugin_info = plugin.info()();
+
+        let icon = self
+            .get_icon()
+            .unwrap_or_else(|| plugin_info.icon.to_string());
+        let image = build_image(&icon, runtime_data.config.grid_icon_size);
+        if runtime_data.config.quick_select_badges {
+            // The badge sits in the corner of the icon, so the titles stay aligned
+            let overlay = gtk::Overlay::builder().child(&image).build();
+            let badge = self.build_badge();
+            badge.set_halign(gtk::Align::End);
+            badge.set_valign(gtk::Align::Start);
+            overlay.add_overlay(&badge);
+            vbox.append(&overlay);
+        } else {
+            vbox.append(&image);
+        }
+
+        let title = gtk::Label::builder()
+            .name(style_names::MATCH_TITLE)
+            .use_markup(self.get_use_pango())
+            .label(self.get_title())
+            .justify(gtk::Justification::Center)
+            .xalign(0.5)
+            .wrap(true)
+            .lines(2)
+            .ellipsize(gtk::pango::EllipsizeMode::End)
+            .build();
+        vbox.append(&title);
+
+        self.add_style_classes(&vbox, &plugin_info.name);
+
+        vbox.into()
+    }
+
+    /// Adds the classes of the plugin, the position in its group and the ones set by the plugin,
+    /// and keeps the `marked` class in sync with the `marked` property
+    fn add_style_classes(&self, widget: &gtk::Box, plugin_name: &str) {
+        widget.add_css_class(&plugin_class(plugin_name));
+        if self.get_first() {
+            widget.add_css_class(style_names::FIRST);
+        }
+        for class in self.get_style_classes() {
+            widget.add_css_class(&class);
+        }
+
+        let update_marked = |widget: &gtk::Box, gmatch: &GMatch| {
+            if gmatch.get_marked() {
+                widget.add_css_class(style_names::MARKED);
+            } else {
+                widget.remove_css_class(style_names::MARKED);
+            }
+        };
+        update_marked(widget, self);
+        self.follow_property(widget, "marked", update_marked);
+    }
+
+    /// The number shown on the first matches in view for the quick-select keybinds
+    fn build_badge(&self) -> gtk::Label {
+        let badge = gtk::Label::builder().name(style_names::QUICK_INDEX).build();
+        let update_badge = |badge: &gtk::Label, gmatch: &GMatch| {
+            let quick_index = gmatch.get_quick_index();
+            badge.set_text(&quick_index.to_string());
+            badge.set_visible(quick_index > 0);
+        };
+        update_badge(&badge, self);
+        self.follow_property(&badge, "quick-index", update_badge);
+        badge
+    }
+
+    /// Calls `update` whenever `property` changes for as long as `widget` exists.
+    ///
+    /// A match outlives its widgets when the view switches between the list and the grid, so the
+    /// handler is disconnected again when the widget goes away.
+    fn follow_property<W, F>(&self, widget: &W, property: &str, update: F)
+    where
+        W: IsA<gtk::Widget>,
+        F: Fn(&W, &GMatch) + 'static,
+    {
+        let handler = self.connect_notify_local(
+            Some(property),
+            clone!(@weak widget => move |gmatch, _| update(&widget, gmatch)),
+        );
+        let handler = Cell::new(Some(handler));
+        widget.connect_destroy(clone!(@weak self as gmatch => move |_| {
+            if let Some(handler) = handler.take() {
+                gmatch.disconnect(handler);
+            }
+        }));
+    }
+}
+
+/// CSS class for the rows of a plugin, `plugin-applications` for "Applications"
+fn plugin_class(plugin_name: &str) -> String {
+    let mut class = String::from(style_names::PLUGIN_PREFIX);
+    for c in plugin_name.chars() {
+        if c.is_ascii_alphanumeric() {
+            class.push(c.to_ascii_lowercase());
+        } else if !class.ends_with('-') {
+            class.push('-');
+        }
+    }
+    class.trim_end_matches('-').to_string()
 }
 
 impl Default for GMatch {
@@ -308,6 +540,12 @@ impl std::fmt::Display for GMatch {
             .field("id", &self.get_id())
             .field("plugin_id", &self.get_plugin_id())
             .field("first", &self.get_first())
+            .field("actions", &self.get_actions().len())
+            .field("data", &self.get_data())
+            .field("completion", &self.get_completion())
+            .field("style_classes", &self.get_style_classes())
+            .field("marked", &self.get_marked())
+            .field("quick_index", &self.get_quick_index())
             .finish()
     }
 }
@@ -329,6 +567,16 @@ impl From<RMatch> for GMatch {
         item.set_use_pango(value.use_pango);
         item.set_icon(from_ropt_to_opt(value.icon));
         item.set_id(value.id.into_rust());
+        item.set_actions(value.actions.into_vec());
+        item.set_data(from_ropt_to_opt(value.data));
+        item.set_completion(from_ropt_to_opt(value.completion));
+        item.set_style_classes(
+            value
+                .style_classes
+                .into_iter()
+                .map(|class| class.into())
+                .collect(),
+        );
 
         item.set_plugin_id(0);
 
@@ -354,6 +602,14 @@ impl From<GMatch> for RMatch {
             use_pango: val.get_use_pango(),
             icon: from_opt_to_ropt(val.get_icon()),
             id: val.get_id().into(),
+            actions: val.get_actions().into(),
+            data: from_opt_to_ropt(val.get_data()),
+            completion: from_opt_to_ropt(val.get_completion()),
+            style_classes: val
+                .get_style_classes()
+                .into_iter()
+                .map(|class| class.into())
+                .collect(),
         }
     }
 }
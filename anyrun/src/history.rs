@@ -0,0 +1,291 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyrun_interface::Match;
+use gtk::glib;
+#[allow(unused_imports)]
+use log::*;
+use serde::{Deserialize, Serialize};
+
+/// Time in seconds after which the weight of a selection is halved
+const HALF_LIFE: f64 = (7 * 24 * 60 * 60) as f64;
+
+/// Identifies a match across queries and restarts
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct HistoryKey {
+    /// Name of the plugin as reported by its `PluginInfo`
+    pub plugin: String,
+    pub id: Option<u64>,
+    pub title: String,
+}
+
+impl HistoryKey {
+    pub fn new(plugin: &str, rmatch: &Match) -> Self {
+        Self {
+            plugin: plugin.to_string(),
+            id: rmatch.id.into_option(),
+            title: rmatch.title.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HistoryEntry {
+    key: HistoryKey,
+    /// The normalized query the match was selected with
+    query: String,
+    score: f64,
+    /// Unix timestamp of the last selection
+    last_used: u64,
+}
+
+impl HistoryEntry {
+    fn decayed_score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_used) as f64;
+        self.score * 0.5f64.powf(age / HALF_LIFE)
+    }
+}
+
+/// Selections made by the user, used to boost matches that were picked before for similar queries
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    /// Maximum amount of entries to keep, `0` disables the history completely
+    size: usize,
+    /// Whether there are selections that are not saved to disk yet
+    changed: bool,
+}
+
+impl History {
+    pub fn load(size: usize) -> Self {
+        let entries = if size == 0 {
+            Vec::new()
+        } else {
+            match fs::read_to_string(history_path()) {
+                Ok(content) => ron::from_str(&content).unwrap_or_else(|why| {
+                    warn!("Failed to parse selection history, starting fresh: {}", why);
+                    Vec::new()
+                }),
+                Err(_) => Vec::new(),
+            }
+        };
+
+        Self {
+            entries,
+            size,
+            changed: false,
+        }
+    }
+
+    /// Remembers that the match identified by `key` was selected for `query`.
+    /// Written to disk by `save_if_changed`, so selecting doesn't wait for the disk.
+    pub fn record(&mut self, key: HistoryKey, query: &str) {
+        if self.size == 0 {
+            return;
+        }
+
+        let query = normalize(query);
+        let now = now();
+
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.key == key && entry.query == query)
+        {
+            Some(entry) => {
+                entry.score = entry.decayed_score(now) + 1.0;
+                entry.last_used = now;
+            }
+            None => self.entries.push(HistoryEntry {
+                key,
+                query,
+                score: 1.0,
+                last_used: now,
+            }),
+        }
+
+        if self.entries.len() > self.size {
+            self.entries.sort_by(|a, b| {
+                b.decayed_score(now)
+                    .partial_cmp(&a.decayed_score(now))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            self.entries.truncate(self.size);
+        }
+
+        self.changed = true;
+    }
+
+    /// How strongly the match identified by `key` should be preferred for `query`.
+    /// Selections made with a query that starts with `query` count towards it.
+    pub fn boost(&self, key: &HistoryKey, query: &str) -> f64 {
+        let query = normalize(query);
+        let now = now();
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.key == *key && entry.query.starts_with(&query))
+            .map(|entry| entry.decayed_score(now))
+            .sum()
+    }
+
+    /// Forgets all selections, including the ones stored on disk
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.changed = false;
+        remove_history_file();
+    }
+
+    /// Writes the selections made since the last save to disk, done once the window is hidden
+    pub fn save_if_changed(&mut self) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+
+        let path = history_path();
+
+        if let Some(dir) = path.parent() {
+            if let Err(why) = fs::create_dir_all(dir) {
+                error!("Failed to create state directory {:?}: {}", dir, why);
+                return;
+            }
+        }
+
+        match ron::to_string(&self.entries) {
+            Ok(content) => {
+                if let Err(why) = fs::write(&path, content) {
+                    error!("Failed to save selection history to {:?}: {}", path, why);
+                }
+            }
+            Err(why) => error!("Failed to serialize selection history: {}", why),
+        }
+    }
+}
+
+pub fn remove_history_file() {
+    let path = history_path();
+    if path.exists() {
+        if let Err(why) = fs::remove_file(&path) {
+            error!("Failed to remove selection history {:?}: {}", path, why);
+        }
+    }
+}
+
+fn history_path() -> PathBuf {
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| glib::home_dir().join(".local/state"))
+        .join("anyrun")
+        .join("history.ron")
+}
+
+fn normalize(query: &str) -> String {
+    query.trim().to_lowercase()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(title: &str) -> HistoryKey {
+        HistoryKey {
+            plugin: "Applications".to_string(),
+            id: None,
+            title: title.to_string(),
+        }
+    }
+
+    fn history(size: usize) -> History {
+        History {
+            entries: Vec::new(),
+            size,
+            changed: false,
+        }
+    }
+
+    #[test]
+    fn score_halves_every_half_life() {
+        let entry = HistoryEntry {
+            key: key("Firefox"),
+            query: "fi".to_string(),
+            score: 4.0,
+            last_used: 1000,
+        };
+        let half_life = HALF_LIFE as u64;
+
+        assert_eq!(entry.decayed_score(1000), 4.0);
+        assert!((entry.decayed_score(1000 + half_life) - 2.0).abs() < 1e-9);
+        assert!((entry.decayed_score(1000 + 2 * half_life) - 1.0).abs() < 1e-9);
+        // A clock that went backwards doesn't make the selection count more
+        assert_eq!(entry.decayed_score(0), 4.0);
+    }
+
+    #[test]
+    fn repeated_selections_add_up() {
+        let mut history = history(10);
+        history.record(key("Firefox"), "fi");
+        history.record(key("Firefox"), "fi");
+        history.record(key("Files"), "fi");
+
+        assert!(history.boost(&key("Firefox"), "fi") > history.boost(&key("Files"), "fi"));
+        assert_eq!(history.boost(&key("Thunderbird"), "fi"), 0.0);
+        assert!(history.changed);
+    }
+
+    #[test]
+    fn boost_counts_longer_queries_and_ignores_case() {
+        let mut history = history(10);
+        history.record(key("Firefox"), "  FireF ");
+
+        assert!(history.boost(&key("Firefox"), "fi") > 0.0);
+        assert!(history.boost(&key("Firefox"), "firef") > 0.0);
+        assert_eq!(history.boost(&key("Firefox"), "firefox"), 0.0);
+        assert_eq!(history.boost(&key("Firefox"), "te"), 0.0);
+    }
+
+    #[test]
+    fn recent_selections_outweigh_old_ones() {
+        let mut history = history(10);
+        history.record(key("Firefox"), "f");
+        history.record(key("Firefox"), "f");
+        history.record(key("Files"), "f");
+        // Both Firefox selections were made three half-lives ago
+        history.entries[0].last_used -= 3 * HALF_LIFE as u64;
+
+        assert!(history.boost(&key("Files"), "f") > history.boost(&key("Firefox"), "f"));
+    }
+
+    #[test]
+    fn lowest_scores_are_dropped_when_full() {
+        let mut history = history(2);
+        history.record(key("Firefox"), "f");
+        history.record(key("Firefox"), "f");
+        history.record(key("Files"), "f");
+        history.entries[1].last_used -= HALF_LIFE as u64;
+        history.record(key("Foot"), "f");
+
+        assert_eq!(history.entries.len(), 2);
+        assert!(history.boost(&key("Firefox"), "f") > 0.0);
+        assert!(history.boost(&key("Foot"), "f") > 0.0);
+        assert_eq!(history.boost(&key("Files"), "f"), 0.0);
+    }
+
+    #[test]
+    fn disabled_history_records_nothing() {
+        let mut history = history(0);
+        history.record(key("Firefox"), "f");
+
+        assert!(history.entries.is_empty());
+        assert!(!history.changed);
+    }
+}
@@ -0,0 +1,150 @@
+use gtk::gdk::{self, Key, ModifierType};
+#[allow(unused_imports)]
+use log::*;
+
+use crate::config::{KeyAction, Keybind};
+
+/// Bindings that are always there, keybinds from the config take precedence over them
+const DEFAULT_KEYBINDS: &[(&str, KeyAction)] = &[
+    ("Escape", KeyAction::Close),
+    ("Down", KeyAction::Next),
+    ("Up", KeyAction::Prev),
+    ("ctrl+j", KeyAction::Next),
+    ("ctrl+k", KeyAction::Prev),
+    ("Page_Down", KeyAction::PageDown),
+    ("Page_Up", KeyAction::PageUp),
+    ("ctrl+Home", KeyAction::First),
+    ("ctrl+End", KeyAction::Last),
+    ("Tab", KeyAction::Complete),
+    ("alt+Return", KeyAction::SecondaryAction),
+    ("alt+KP_Enter", KeyAction::SecondaryAction),
+    ("ctrl+space", KeyAction::ToggleMark),
+    ("alt+1", KeyAction::ActivateNth(1)),
+    ("alt+2", KeyAction::ActivateNth(2)),
+    ("alt+3", KeyAction::ActivateNth(3)),
+    ("alt+4", KeyAction::ActivateNth(4)),
+    ("alt+5", KeyAction::ActivateNth(5)),
+    ("alt+6", KeyAction::ActivateNth(6)),
+    ("alt+7", KeyAction::ActivateNth(7)),
+    ("alt+8", KeyAction::ActivateNth(8)),
+    ("alt+9", KeyAction::ActivateNth(9)),
+];
+
+/// Key combos resolved from the config
+pub struct Keybinds {
+    binds: Vec<(Key, ModifierType, KeyAction)>,
+}
+
+impl Keybinds {
+    pub fn new(keybinds: &[Keybind]) -> Self {
+        let binds = keybinds
+            .iter()
+            .map(|keybind| (keybind.key.as_str(), keybind.action))
+            .chain(DEFAULT_KEYBINDS.iter().copied())
+            .filter_map(|(combo, action)| match parse_combo(combo) {
+                Some((key, mods)) => Some((key, mods, action)),
+                None => {
+                    warn!("Invalid key combo in keybinds: {}", combo);
+                    None
+                }
+            })
+            .collect();
+
+        Self { binds }
+    }
+
+    /// The action bound to a key press. Layouts without Latin letters, like Cyrillic ones, fall back
+    /// to the physical key, so `ctrl+j` still works on the key labeled J.
+    pub fn action(
+        &self,
+        display: &gdk::Display,
+        keyval: Key,
+        keycode: u32,
+        state: ModifierType,
+    ) -> Option<KeyAction> {
+        let keyval = keyval.to_lower();
+        let mods = state
+            & (ModifierType::CONTROL_MASK
+                | ModifierType::SHIFT_MASK
+                | ModifierType::ALT_MASK
+                | ModifierType::SUPER_MASK);
+
+        let bound = self
+            .binds
+            .iter()
+            .find(|(key, bind_mods, _)| *key == keyval && *bind_mods == mods);
+
+        if bound.is_some() || keyval.to_unicode().map_or(true, |c| c.is_ascii()) {
+            return bound.map(|(_, _, action)| *action);
+        }
+
+        self.binds
+            .iter()
+            .find(|(key, bind_mods, _)| {
+                *bind_mods == mods
+                    && display
+                        .map_keyval(*key)
+                        .is_some_and(|keys| keys.iter().any(|key| key.keycode() == keycode))
+            })
+            .map(|(_, _, action)| *action)
+    }
+}
+
+fn parse_combo(combo: &str) -> Option<(Key, ModifierType)> {
+    let mut parts: Vec<&str> = combo.split('+').map(str::trim).collect();
+    let key = Key::from_name(parts.pop()?)?;
+
+    let mut mods = ModifierType::empty();
+    for modifier in parts {
+        mods |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "control" => ModifierType::CONTROL_MASK,
+            "shift" => ModifierType::SHIFT_MASK,
+            "alt" => ModifierType::ALT_MASK,
+            "super" | "logo" => ModifierType::SUPER_MASK,
+            _ => return None,
+        };
+    }
+
+    Some((key.to_lower(), mods))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_without_modifiers() {
+        assert_eq!(
+            parse_combo("Escape"),
+            Some((Key::Escape, ModifierType::empty()))
+        );
+    }
+
+    #[test]
+    fn parses_modifiers_in_any_case_and_spacing() {
+        assert_eq!(
+            parse_combo("Ctrl + shift+K"),
+            Some((
+                Key::k,
+                ModifierType::CONTROL_MASK | ModifierType::SHIFT_MASK
+            ))
+        );
+        assert_eq!(
+            parse_combo("logo+alt+1"),
+            Some((Key::_1, ModifierType::SUPER_MASK | ModifierType::ALT_MASK))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_modifiers() {
+        assert_eq!(parse_combo("hyper+j"), None);
+        assert_eq!(parse_combo("ctrl+NoSuchKey"), None);
+    }
+
+    #[test]
+    fn default_keybinds_are_valid() {
+        for (combo, _) in DEFAULT_KEYBINDS {
+            assert!(parse_combo(combo).is_some(), "{}", combo);
+        }
+    }
+}
@@ -1,8 +1,13 @@
 mod config;
 mod gmatch;
+mod history;
+mod keybinds;
 mod plugins;
+mod spawn;
+mod style;
 mod ui;
 mod utils;
+mod view;
 
 use std::{cell::RefCell, os::unix::net::UnixStream, rc::Rc};
 
@@ -18,9 +23,12 @@ use gtk::{
 use log::*;
 
 use config::*;
+use history::History;
 use plugins::*;
+use style::load_styles;
 use ui::*;
 use utils::*;
+use view::MatchView;
 
 fn send_command(command: &str) {
     use std::io::Write;
@@ -56,6 +64,7 @@ fn main() -> Result<glib::ExitCode, glib::Error> {
                 Command::Hide => send_command("hide"),
                 Command::Toggle => send_command("toggle"),
                 Command::Close => send_command("close"),
+                Command::ClearHistory => send_command("clear-history"),
             }
         } else {
             send_command("show")
@@ -64,6 +73,11 @@ fn main() -> Result<glib::ExitCode, glib::Error> {
         return Ok(glib::ExitCode::SUCCESS);
     }
 
+    if let Some(Command::ClearHistory) = args.command {
+        history::remove_history_file();
+        return Ok(glib::ExitCode::SUCCESS);
+    }
+
     debug!("Running as main instance");
 
     let socket_path = socket_path();
@@ -82,46 +96,10 @@ fn main() -> Result<glib::ExitCode, glib::Error> {
         .expect("Failed to add address to the service");
     debug!("Created socket at {}", socket_path.to_string_lossy());
 
-    let daemon = config.daemon;
-
-    service.connect_incoming(
-        clone!(@weak app => @default-return true, move |_, connection, _| {
-            debug!("NEW INCOME");
-            let command = read_from_stream(&connection.input_stream());
-            debug!("> {:?}", command);
-
-            let windows = app.windows();
-            let window = windows.first();
-            if let Some(window) = window {
-                match command.as_str() {
-                    "show" => window.show(),
-                    "hide" => if daemon {window.hide()} else {send_command("close")},
-                    "toggle" => if window.is_visible() {send_command("hide")} else {send_command("show")},
-                    "close" => window.close(),
-                    _ => error!("Unknown command received: {:?}", command),
-                }
-            }
-            true
-        }),
-    );
-
-    service.start();
-    debug!("Service started");
-
     let app_state = gio::Settings::new(APP_ID);
 
     let display = gdk::Display::default().expect("No display found");
-    let monitor = display
-        .monitors()
-        .into_iter()
-        .filter_map(|m| m.ok())
-        .peekable()
-        .peek()
-        .expect("No monitor found")
-        .clone()
-        .downcast::<gdk::Monitor>()
-        .expect("Can't downcast Object to Monitor");
-    let geometry = monitor.geometry();
+    let geometry = initial_geometry(&display, &config.monitor);
 
     let list_store = gio::ListStore::builder()
         .item_type(GMatch::static_type())
@@ -132,60 +110,120 @@ fn main() -> Result<glib::ExitCode, glib::Error> {
         .iter()
         .map(|filename| load_plugin(filename, &config_dir))
         .collect();
+    let plugin_ranks = plugin_ranks(&config);
+    let history = History::load(config.history_size);
+    let daemon = config.daemon;
 
     let runtime_data = Rc::new(RefCell::new(RuntimeData {
         exclusive: None,
+        pinned: None,
         post_run_action: PostRunAction::None,
         config,
         error_label,
+        banner: None,
+        window: None,
+        view: None,
+        prompt: None,
+        footer: None,
+        preview: None,
+        css_errors: None,
+        css_provider: None,
+        css_monitor: None,
+        banner_timeout: None,
         config_dir,
         geometry,
         list_store,
         plugins,
+        plugin_ranks,
         app_state,
+        history,
+        refresh: Refresh::default(),
     }));
 
+    watch_plugins(runtime_data.clone());
+
+    service.connect_incoming(
+        clone!(@weak app, @weak runtime_data => @default-return true, move |_, connection, _| {
+            debug!("NEW INCOME");
+            let command = read_from_stream(&connection.input_stream());
+            debug!("> {:?}", command);
+
+            if command == "clear-history" {
+                runtime_data.borrow_mut().history.clear();
+                return true;
+            }
+
+            let windows = app.windows();
+            let window = windows.first();
+            if let Some(window) = window {
+                match command.as_str() {
+                    "show" => window.show(),
+                    "hide" => if daemon {window.hide()} else {send_command("close")},
+                    "toggle" => if window.is_visible() {send_command("hide")} else {send_command("show")},
+                    "close" => window.close(),
+                    _ => error!("Unknown command received: {:?}", command),
+                }
+            }
+            true
+        }),
+    );
+
+    service.start();
+    debug!("Service started");
+
     app.connect_activate(
         clone!(@weak runtime_data => move |app| activate(app, runtime_data.clone())),
     );
     let exit_code = app.run();
 
-    handle_post_run_action(&mut runtime_data.borrow_mut().post_run_action, true);
+    handle_post_run_action(&mut runtime_data.borrow_mut(), true);
+    runtime_data.borrow_mut().history.save_if_changed();
 
     Ok(exit_code)
 }
 
 fn activate(app: &impl IsA<gtk::Application>, runtime_data: Rc<RefCell<RuntimeData>>) {
-    load_custom_css(runtime_data.clone());
-
-    let main_list = Rc::new(
-        gtk::ListBox::builder()
-            .selection_mode(gtk::SelectionMode::Browse)
-            .name(style_names::MAIN)
-            .build(),
-    );
-    
     let list_eck = gtk::EventControllerKey::new();
 
     let list_store = runtime_data.clone().borrow().list_store.clone();
 
-    main_list.bind_model(
-        Some(&list_store),
-        clone!(@strong runtime_data => move |match_row| {
-            match_row
+    let main_list = Rc::new(MatchView::new(
+        &list_store,
+        runtime_data.borrow().config.grid_columns,
+        clone!(@strong runtime_data => move |match_row, grid| {
+            let gmatch = match_row
                 .clone()
                 .downcast::<GMatch>()
-                .expect("Can't downcast glib::Object to GMatch")
-                .to_widget(runtime_data.clone())
+                .expect("Can't downcast glib::Object to GMatch");
+            if grid {
+                gmatch.to_grid_widget(runtime_data.clone())
+            } else {
+                gmatch.to_widget(runtime_data.clone())
+            }
         }),
-    );
+    ));
+    // Without a query running yet, every plugin decides
+    let grid = {
+        let runtime_data = runtime_data.borrow();
+        runtime_data.config.use_grid(0..runtime_data.plugins.len())
+    };
+    main_list.set_grid(grid);
+    runtime_data.borrow_mut().view = Some(main_list.clone());
 
     let window = setup_main_window(app, runtime_data.clone());
+    if runtime_data.borrow().config.daemon {
+        watch_monitors(window.clone(), runtime_data.clone());
+    }
 
-    let (entry, entry_eck) = setup_entry(runtime_data.clone());
-    entry.set_key_capture_widget(Some(main_list.upcast_ref::<gtk::Widget>()));
+    let entry = setup_entry(runtime_data.clone());
+    entry.set_key_capture_widget(Some(main_list.widget()));
 
-    connect_entry_with_window_key_press_events(entry.clone(), entry_eck, &main_list, list_eck);
+    connect_entry_with_window_key_press_events(
+        entry.clone(),
+        &main_list,
+        list_eck,
+        runtime_data.clone(),
+    );
 
     if runtime_data.borrow().config.save_entry_state {
         let app_state = runtime_data.borrow().app_state.clone();
@@ -195,23 +233,34 @@ fn activate(app: &impl IsA<gtk::Application>, runtime_data: Rc<RefCell<RuntimeDa
 
     list_store.connect_items_changed(
         clone!(@weak entry, @strong main_list, @weak runtime_data => move |_, _, _, _| {
-            main_list.select_row(main_list.row_at_index(0).as_ref())
+            if main_list.selected_index().is_none() {
+                main_list.select(0)
+            }
         }),
     );
 
     setup_activation(entry.clone(), main_list.clone(), runtime_data.clone());
+    setup_action_menu(entry.clone(), main_list.clone(), runtime_data.clone());
+    setup_multi_select(main_list.clone(), runtime_data.clone());
 
     if runtime_data.borrow().config.show_results_immediately {
         refresh_matches(&entry.text(), runtime_data.clone());
     }
 
+    let preview = setup_preview(main_list.clone(), runtime_data.clone());
+
     configure_main_window(
         window.clone(),
         runtime_data.clone(),
         entry.clone(),
         main_list.clone(),
+        preview,
     );
 
+    setup_quick_select(main_list.clone(), runtime_data.clone());
+    load_styles(&runtime_data);
+    update_prompt(&entry.text(), &runtime_data);
+
     if !runtime_data.borrow().config.daemon {
         window.present();
     }
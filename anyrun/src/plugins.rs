@@ -1,28 +1,233 @@
-use std::{cell::RefCell, env, path::PathBuf, rc::Rc, time::Duration};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    env,
+    io::Read,
+    mem::ManuallyDrop,
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        net::UnixStream,
+    },
+    path::PathBuf,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
+use abi_stable::std_types::RVec;
 use anyrun_interface::{Match, PluginRef as Plugin, PollResult};
 #[allow(unused_imports)]
 use log::*;
 
 use crate::{
-    config::{default_config_dir, RuntimeData},
+    config::{default_config_dir, style_names, Config, RuntimeData},
     gmatch::GMatch,
+    history::{History, HistoryKey},
+    ui::{update_footer, update_prompt},
 };
 
 use gtk::{
     gio,
     glib::{self, clone},
+    prelude::*,
 };
 
-fn handle_matches(plugin_id: u64, matches: &[Match], list_store: gio::ListStore) {
-    for (index, rmatch) in matches.iter().enumerate() {
-        let gmatch = GMatch::from(rmatch.clone());
-        gmatch.set_plugin_id(plugin_id);
-        gmatch.set_first(index == 0);
-        list_store.append(&gmatch);
+/// The query the plugins are currently working on
+#[derive(Default)]
+pub struct Refresh {
+    input: String,
+    /// Ids returned by `get_matches` for the plugins that have not finished yet, indexed by plugin id
+    tasks: Vec<Option<u64>>,
+    /// Amount of rows every plugin has in the list, indexed by plugin id
+    slots: Vec<u32>,
+    /// Whether the rows of a plugin still belong to an earlier query, indexed by plugin id
+    stale: Vec<bool>,
+    /// Whether the rows left over from the previous query were removed already
+    swapped: bool,
+    started: Option<Instant>,
+    /// How long it took until all plugins were done with the query
+    took: Option<Duration>,
+}
+
+impl Refresh {
+    /// Ids of the plugins that are still working on the query
+    pub fn pending(&self) -> impl Iterator<Item = usize> + '_ {
+        self.tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, task)| task.is_some())
+            .map(|(plugin_id, _)| plugin_id)
+    }
+
+    /// Amount of rows that belong to this query, leaving out the ones kept from the previous one
+    pub fn count(&self) -> u32 {
+        self.slots
+            .iter()
+            .zip(&self.stale)
+            .filter(|(_, stale)| !**stale)
+            .map(|(count, _)| count)
+            .sum()
+    }
+
+    pub fn took(&self) -> Option<Duration> {
+        self.took
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.is_some()
+    }
+}
+
+/// Puts the matches of a plugin into the group reserved for it, so the layout of the list does not
+/// depend on which plugin happened to answer first.
+///
+/// The first matches for a query replace the rows the plugin had for the previous one, later
+/// batches are appended to them.
+fn handle_matches(plugin_id: usize, matches: &[Match], runtime_data: &Rc<RefCell<RuntimeData>>) {
+    let (list_store, position, replaced, gmatches) = {
+        let runtime_data = runtime_data.borrow();
+        let refresh = &runtime_data.refresh;
+        let ranks = &runtime_data.plugin_ranks;
+
+        let matches = sort_by_history(
+            &runtime_data.plugins[plugin_id],
+            matches,
+            &runtime_data.history,
+            &refresh.input,
+        );
+
+        let group_start = refresh
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| ranks[*id] < ranks[plugin_id])
+            .map(|(_, count)| count)
+            .sum::<u32>();
+
+        let (position, replaced) = if refresh.stale[plugin_id] {
+            (group_start, refresh.slots[plugin_id])
+        } else {
+            (group_start + refresh.slots[plugin_id], 0)
+        };
+        let first_batch = replaced > 0 || refresh.slots[plugin_id] == 0;
+
+        let gmatches: Vec<GMatch> = matches
+            .into_iter()
+            .enumerate()
+            .map(|(index, rmatch)| {
+                let gmatch = GMatch::from(rmatch);
+                gmatch.set_plugin_id(plugin_id as u64);
+                gmatch.set_first(first_batch && index == 0);
+                gmatch
+            })
+            .collect();
+
+        (
+            runtime_data.list_store.clone(),
+            position,
+            replaced,
+            gmatches,
+        )
+    };
+
+    {
+        let refresh = &mut runtime_data.borrow_mut().refresh;
+        refresh.stale[plugin_id] = false;
+        refresh.slots[plugin_id] = refresh.slots[plugin_id] - replaced + gmatches.len() as u32;
+    }
+    // The runtime data must not be borrowed here, rows are built as soon as they are inserted
+    update_rows(&list_store, position, replaced, &gmatches);
+}
+
+/// Replaces `replaced` rows starting at `position` with `gmatches`, leaving the rows that did not
+/// change untouched so their widgets and the selection survive.
+fn update_rows(list_store: &gio::ListStore, position: u32, replaced: u32, gmatches: &[GMatch]) {
+    let old: Vec<GMatch> = (position..position + replaced)
+        .filter_map(|index| list_store.item(index))
+        .map(|item| {
+            item.downcast::<GMatch>()
+                .expect("Can't downcast glib::Object to GMatch")
+        })
+        .collect();
+
+    let prefix = old
+        .iter()
+        .zip(gmatches)
+        .take_while(|(old, new)| old.same_as(new))
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(gmatches[prefix..].iter().rev())
+        .take_while(|(old, new)| old.same_as(new))
+        .count();
+
+    let removals = old.len() - prefix - suffix;
+    let additions = &gmatches[prefix..gmatches.len() - suffix];
+
+    if removals > 0 || !additions.is_empty() {
+        list_store.splice(position + prefix as u32, removals as u32, additions);
     }
 }
 
+/// Removes the rows left over from the previous query, except the ones of `answering`, which
+/// replaces them with its matches so unchanged rows stay. Done once the first plugin answers, so
+/// the list does not flash empty in between. Plugins that are still searching lose their old rows
+/// too, as those don't match the query anymore.
+fn swap_rows(answering: usize, runtime_data: &Rc<RefCell<RuntimeData>>) {
+    let leftover: Vec<usize> = {
+        let refresh = &mut runtime_data.borrow_mut().refresh;
+        refresh.swapped = true;
+        (0..refresh.stale.len())
+            .filter(|plugin_id| refresh.stale[*plugin_id] && *plugin_id != answering)
+            .collect()
+    };
+
+    for plugin_id in leftover {
+        handle_matches(plugin_id, &[], runtime_data);
+    }
+}
+
+/// Moves the matches that were selected before for a similar query to the top of their group,
+/// otherwise keeping the order the plugin returned them in.
+fn sort_by_history(
+    plugin: &Plugin,
+    matches: &[Match],
+    history: &History,
+    query: &str,
+) -> Vec<Match> {
+    let plugin_name = plugin.info()().name;
+
+    let mut boosted: Vec<(f64, Match)> = matches
+        .iter()
+        .map(|rmatch| {
+            let boost = history.boost(&HistoryKey::new(&plugin_name, rmatch), query);
+            (boost, rmatch.clone())
+        })
+        .collect();
+    boosted.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+    boosted.into_iter().map(|(_, rmatch)| rmatch).collect()
+}
+
+/// Computes the position of every plugin group in the list.
+///
+/// Plugins mentioned in `plugin_priority` come first in that order, the rest follow in the order
+/// of `plugins`.
+pub fn plugin_ranks(config: &Config) -> Vec<usize> {
+    config
+        .plugins
+        .iter()
+        .enumerate()
+        .map(|(index, plugin)| {
+            config
+                .plugin_priority
+                .iter()
+                .position(|prioritized| prioritized == plugin)
+                .unwrap_or(config.plugin_priority.len() + index)
+        })
+        .collect()
+}
+
 /// Loads a plugin from the specified path or from the provided directories if the path is not absolute.
 ///
 /// # Arguments
@@ -73,52 +278,278 @@ pub fn load_plugin(plugin_path: &PathBuf, config_dir: &PathBuf) -> Plugin {
     plugin
 }
 
-pub fn refresh_matches(input: &str, runtime_data: Rc<RefCell<RuntimeData>>) {
-    let list_store = runtime_data.borrow().list_store.clone();
-    list_store.remove_all();
+/// The plugins a query goes to with their ids, only the exclusive or pinned one if there is one
+pub fn queried_plugins(runtime_data: &RuntimeData) -> Vec<(usize, Plugin)> {
+    match runtime_data.exclusive.or(runtime_data.pinned) {
+        Some(exclusive_plugin) => {
+            let exclusive_plugin_id = runtime_data
+                .plugins
+                .iter()
+                .position(|p| p.info() == exclusive_plugin.info())
+                .unwrap_or_default();
 
-    let mut exclusive_plugin_id = None;
+            vec![(exclusive_plugin_id, exclusive_plugin)]
+        }
+        None => runtime_data.plugins.iter().copied().enumerate().collect(),
+    }
+}
 
+pub fn refresh_matches(input: &str, runtime_data: Rc<RefCell<RuntimeData>>) {
     let plugins = runtime_data.borrow().plugins.clone();
 
-    let plugins_to_use = if let Some(exclusive_plugin) = runtime_data.borrow().exclusive.as_ref() {
-        exclusive_plugin_id = plugins
-            .iter()
-            .position(|p| p.info() == exclusive_plugin.info());
+    let (exclusive, plugins_to_use) = {
+        let runtime_data = runtime_data.borrow();
+        (
+            runtime_data.exclusive.or(runtime_data.pinned),
+            queried_plugins(&runtime_data),
+        )
+    };
+
+    update_prompt(input, &runtime_data);
+
+    // Switched before any rows are built for the query, the runtime data must not be borrowed
+    let (view, grid) = {
+        let runtime_data = runtime_data.borrow();
+        let grid = runtime_data
+            .config
+            .use_grid(plugins_to_use.iter().map(|(plugin_id, _)| *plugin_id));
+        (runtime_data.view.clone(), grid)
+    };
+    if let Some(view) = view {
+        view.set_grid(grid);
+    }
 
-        vec![*exclusive_plugin]
+    if let Some(window) = &runtime_data.borrow().window {
+        if exclusive.is_some() {
+            window.add_css_class(style_names::EXCLUSIVE);
+        } else {
+            window.remove_css_class(style_names::EXCLUSIVE);
+        }
+    }
+
+    let previous = std::mem::take(&mut runtime_data.borrow_mut().refresh);
+    for (plugin_id, task) in previous.tasks.into_iter().enumerate() {
+        if let Some(task) = task {
+            if let Some(cancel_matches) = plugins[plugin_id].cancel_matches() {
+                cancel_matches(task);
+            }
+        }
+    }
+
+    // The rows of the previous query stay until the new results arrive
+    let slots = if previous.slots.len() == plugins.len() {
+        previous.slots
     } else {
-        plugins.to_vec()
+        vec![0; plugins.len()]
+    };
+    let mut refresh = Refresh {
+        input: input.to_string(),
+        tasks: vec![None; plugins.len()],
+        stale: slots.iter().map(|count| *count > 0).collect(),
+        slots,
+        swapped: false,
+        started: Some(Instant::now()),
+        took: None,
     };
+    for (plugin_id, plugin) in &plugins_to_use {
+        refresh.tasks[*plugin_id] = Some(plugin.get_matches()(input.into()));
+    }
+    runtime_data.borrow_mut().refresh = refresh;
+    update_footer(&runtime_data);
 
-    for (plugin_id, plugin) in plugins_to_use.iter().enumerate() {
-        let id = plugin.get_matches()(input.into());
+    // Plugins that can notify about new results are polled by the watchers set up in `watch_plugins`
+    for (plugin_id, plugin) in plugins_to_use {
+        if notify_fd(&plugin) >= 0 {
+            continue;
+        }
 
+        let task = runtime_data.borrow().refresh.tasks[plugin_id];
         glib::timeout_add_local(
             Duration::from_millis(1),
-            clone!(@strong list_store, @strong plugin => move || {
-                async_match(&plugin, id, |matches| {
-                    handle_matches(
-                        exclusive_plugin_id.unwrap_or(plugin_id) as u64,
-                        matches,
-                        list_store.clone(),
-                    )
-                })
+            clone!(@weak runtime_data => @default-return glib::ControlFlow::Break, move || {
+                if runtime_data.borrow().refresh.tasks[plugin_id] != task {
+                    return glib::ControlFlow::Break;
+                }
+                poll_plugin(plugin_id, &runtime_data)
             }),
         );
     }
 }
 
-fn async_match<F>(plugin: &Plugin, id: u64, mut func: F) -> glib::ControlFlow
-where
-    F: FnMut(&[Match]),
-{
-    match plugin.poll_matches()(id) {
-        PollResult::Ready(matches) => {
-            func(&matches);
-            glib::ControlFlow::Break
+/// Starts polling every plugin that provides a notification file descriptor whenever it becomes
+/// readable.
+pub fn watch_plugins(runtime_data: Rc<RefCell<RuntimeData>>) {
+    let plugins = runtime_data.borrow().plugins.clone();
+
+    for (plugin_id, plugin) in plugins.into_iter().enumerate() {
+        let fd = notify_fd(&plugin);
+        if fd < 0 {
+            continue;
+        }
+
+        glib::source::unix_fd_add_local(
+            fd,
+            glib::IOCondition::IN,
+            clone!(@weak runtime_data => @default-return glib::ControlFlow::Break, move |fd, _| {
+                drain_notifications(fd);
+                poll_plugin(plugin_id, &runtime_data);
+                glib::ControlFlow::Continue
+            }),
+        );
+    }
+}
+
+/// The notification fd of the plugin, negative for plugins that have to be polled on a timer
+fn notify_fd(plugin: &Plugin) -> RawFd {
+    plugin.notify_fd().map_or(-1, |notify_fd| notify_fd())
+}
+
+fn drain_notifications(fd: RawFd) {
+    // The descriptor belongs to the plugin, so it must not be closed here
+    let mut stream = ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(fd) });
+    let mut buf = [0; 64];
+    while matches!(stream.read(&mut buf), Ok(count) if count > 0) {}
+}
+
+/// Picks up the results of the current query from the plugin, if it is still working on one
+fn poll_plugin(plugin_id: usize, runtime_data: &Rc<RefCell<RuntimeData>>) -> glib::ControlFlow {
+    let (plugin, task) = {
+        let runtime_data = runtime_data.borrow();
+        (
+            runtime_data.plugins[plugin_id],
+            runtime_data.refresh.tasks.get(plugin_id).copied().flatten(),
+        )
+    };
+    let Some(task) = task else {
+        return glib::ControlFlow::Break;
+    };
+
+    let (matches, finished) = match plugin.poll_matches()(task) {
+        PollResult::Ready(matches) => (matches, true),
+        PollResult::Partial(matches) => (matches, false),
+        PollResult::Pending => return glib::ControlFlow::Continue,
+        PollResult::Cancelled => (RVec::new(), true),
+    };
+
+    if !runtime_data.borrow().refresh.swapped {
+        swap_rows(plugin_id, runtime_data);
+    }
+
+    if finished {
+        let refresh = &mut runtime_data.borrow_mut().refresh;
+        refresh.tasks[plugin_id] = None;
+        if refresh.pending().next().is_none() {
+            refresh.took = refresh.started.map(|started| started.elapsed());
+        }
+    }
+    // A plugin that finished without any matches still has to get rid of its old rows
+    if !matches.is_empty() || (finished && runtime_data.borrow().refresh.stale[plugin_id]) {
+        handle_matches(plugin_id, &matches, runtime_data);
+    }
+    update_footer(runtime_data);
+
+    if finished {
+        glib::ControlFlow::Break
+    } else {
+        glib::ControlFlow::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(plugins: &[&str], plugin_priority: &[&str]) -> Config {
+        Config {
+            plugins: plugins.iter().map(PathBuf::from).collect(),
+            plugin_priority: plugin_priority.iter().map(PathBuf::from).collect(),
+            ..Config::default()
         }
-        PollResult::Pending => glib::ControlFlow::Continue,
-        PollResult::Cancelled => glib::ControlFlow::Break,
+    }
+
+    #[test]
+    fn ranks_follow_load_order_without_priority() {
+        let config = config(&["liba.so", "libb.so", "libc.so"], &[]);
+        assert_eq!(plugin_ranks(&config), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn prioritized_plugins_come_first() {
+        let config = config(&["liba.so", "libb.so", "libc.so"], &["libc.so", "liba.so"]);
+        let ranks = plugin_ranks(&config);
+
+        assert_eq!(ranks[2], 0);
+        assert_eq!(ranks[0], 1);
+        assert!(ranks[1] > ranks[0]);
+    }
+
+    #[test]
+    fn unknown_priority_entries_are_ignored() {
+        let config = config(&["liba.so", "libb.so"], &["libmissing.so", "libb.so"]);
+        let ranks = plugin_ranks(&config);
+
+        assert!(ranks[1] < ranks[0]);
+    }
+
+    fn gmatch(title: &str) -> GMatch {
+        GMatch::from(Match {
+            title: title.into(),
+            ..Match::default()
+        })
+    }
+
+    fn store(titles: &[&str]) -> gio::ListStore {
+        let list_store = gio::ListStore::new::<GMatch>();
+        for title in titles {
+            list_store.append(&gmatch(title));
+        }
+        list_store
+    }
+
+    fn item(list_store: &gio::ListStore, position: u32) -> GMatch {
+        list_store.item(position).and_downcast::<GMatch>().unwrap()
+    }
+
+    fn titles(list_store: &gio::ListStore) -> Vec<String> {
+        (0..list_store.n_items())
+            .map(|position| item(list_store, position).get_title())
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_rows_are_kept() {
+        let list_store = store(&["a", "b", "c", "d", "e"]);
+        let first = item(&list_store, 1);
+        let last = item(&list_store, 3);
+
+        update_rows(&list_store, 1, 3, &[gmatch("b"), gmatch("x"), gmatch("d")]);
+
+        assert_eq!(titles(&list_store), ["a", "b", "x", "d", "e"]);
+        assert_eq!(item(&list_store, 1), first);
+        assert_eq!(item(&list_store, 3), last);
+    }
+
+    #[test]
+    fn identical_rows_leave_the_store_alone() {
+        let list_store = store(&["a", "b"]);
+        let changes = Rc::new(RefCell::new(0));
+        list_store.connect_items_changed(clone!(@strong changes => move |_, _, _, _| {
+            *changes.borrow_mut() += 1;
+        }));
+
+        update_rows(&list_store, 0, 2, &[gmatch("a"), gmatch("b")]);
+
+        assert_eq!(*changes.borrow(), 0);
+    }
+
+    #[test]
+    fn rows_are_appended_and_removed_within_the_group() {
+        let list_store = store(&["a", "b", "z"]);
+
+        update_rows(&list_store, 2, 0, &[gmatch("c")]);
+        assert_eq!(titles(&list_store), ["a", "b", "c", "z"]);
+
+        update_rows(&list_store, 0, 3, &[gmatch("b")]);
+        assert_eq!(titles(&list_store), ["b", "z"]);
     }
 }
@@ -0,0 +1,85 @@
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread,
+};
+
+use abi_stable::std_types::ROption;
+use anyrun_interface::SpawnRequest;
+use gtk::glib;
+#[allow(unused_imports)]
+use log::*;
+use nix::unistd;
+
+use crate::config::Config;
+
+/// Launches the program of a `HandleResult::Spawn` in its own session, so it outlives anyrun and
+/// doesn't get the signals meant for it.
+///
+/// Returns a message for the user when the program could not be started.
+pub fn spawn(request: SpawnRequest, config: &Config) -> Result<(), String> {
+    let mut argv: Vec<String> = request.argv.into_iter().map(String::from).collect();
+
+    if argv.is_empty() {
+        return Err("The plugin asked to launch a program without giving one".to_string());
+    }
+
+    if request.terminal {
+        argv.splice(0..0, config.terminal.iter().cloned());
+    }
+
+    if config.systemd_scope {
+        argv.splice(
+            0..0,
+            [
+                "systemd-run",
+                "--user",
+                "--scope",
+                "--slice=app.slice",
+                "--collect",
+                "--quiet",
+                "--",
+            ]
+            .map(String::from),
+        );
+    }
+
+    let mut command = Command::new(&argv[0]);
+    command
+        .args(&argv[1..])
+        .envs(
+            request
+                .env
+                .into_iter()
+                .map(|pair| (pair.0.into_string(), pair.1.into_string())),
+        )
+        .current_dir(match request.cwd {
+            ROption::RSome(cwd) => cwd.into_string().into(),
+            ROption::RNone => glib::home_dir(),
+        })
+        .stdin(Stdio::null())
+        .stdout(Stdio::null());
+
+    // SAFETY: `setsid` is async-signal-safe
+    unsafe {
+        command.pre_exec(|| unistd::setsid().map(|_| ()).map_err(io::Error::from));
+    }
+
+    match command.spawn() {
+        Ok(mut child) => {
+            info!("Spawned {:?} with pid {}", argv, child.id());
+            // Reaps the child so it doesn't linger as a zombie while anyrun keeps running as a daemon
+            thread::spawn(move || {
+                if let Err(why) = child.wait() {
+                    error!("Failed to wait for spawned program: {}", why);
+                }
+            });
+            Ok(())
+        }
+        Err(why) => {
+            error!("Failed to spawn {:?}: {}", argv, why);
+            Err(format!("Failed to launch {}: {}", argv[0], why))
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use gtk::{
+    gdk, gio,
+    glib::{self, clone},
+    prelude::*,
+};
+use log::*;
+
+use crate::config::{RuntimeData, Theme};
+
+const LIGHT_CSS: &str = include_str!("../res/themes/light.css");
+const DARK_CSS: &str = include_str!("../res/themes/dark.css");
+const HIGH_CONTRAST_CSS: &str = include_str!("../res/themes/high-contrast.css");
+
+fn theme_css(theme: Theme) -> Option<&'static str> {
+    match theme {
+        Theme::System => None,
+        Theme::Light => Some(LIGHT_CSS),
+        Theme::Dark => Some(DARK_CSS),
+        Theme::HighContrast => Some(HIGH_CONTRAST_CSS),
+    }
+}
+
+/// Applies the theme and the `style.css` of the user on top of it, and reloads the latter whenever it changes.
+///
+/// Needs the window to be set up to report errors in the CSS.
+pub fn load_styles(runtime_data: &Rc<RefCell<RuntimeData>>) {
+    // The providers stay in place for the lifetime of the display, the watcher keeps them up to date
+    if runtime_data.borrow().css_provider.is_some() {
+        return;
+    }
+
+    let display = gdk::Display::default().expect("Failed to get GDK display for CSS provider!");
+
+    if let Some(css) = theme_css(runtime_data.borrow().config.theme) {
+        let provider = gtk::CssProvider::new();
+        provider.load_from_data(css);
+        gtk::style_context_add_provider_for_display(
+            &display,
+            &provider,
+            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION - 1,
+        );
+    }
+
+    let provider = gtk::CssProvider::new();
+    gtk::style_context_add_provider_for_display(
+        &display,
+        &provider,
+        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+    );
+
+    let errors: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+    provider.connect_parsing_error(clone!(@strong errors => move |_, section, error| {
+        let location = section.start_location();
+        errors.borrow_mut().push(format!(
+            "style.css:{}:{}: {}",
+            location.lines() + 1,
+            location.line_chars() + 1,
+            error
+        ));
+    }));
+
+    let css_path = runtime_data.borrow().config_dir.join("style.css");
+    reload_css(&provider, &css_path, &errors, runtime_data);
+
+    match gio::File::for_path(&css_path)
+        .monitor_file(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+    {
+        Ok(monitor) => {
+            monitor.connect_changed(
+                clone!(@weak provider, @strong errors, @weak runtime_data => move |_, _, _, event| {
+                    // Editors save by writing in place or by moving a new file over the old one
+                    if matches!(
+                        event,
+                        gio::FileMonitorEvent::ChangesDoneHint
+                            | gio::FileMonitorEvent::Deleted
+                            | gio::FileMonitorEvent::MovedIn
+                            | gio::FileMonitorEvent::Renamed
+                    ) {
+                        reload_css(&provider, &css_path, &errors, &runtime_data);
+                    }
+                }),
+            );
+            runtime_data.borrow_mut().css_monitor = Some(monitor);
+        }
+        Err(why) => warn!("Failed to watch {:?} for changes: {}", css_path, why),
+    }
+
+    runtime_data.borrow_mut().css_provider = Some(provider);
+}
+
+fn reload_css(
+    provider: &gtk::CssProvider,
+    css_path: &Path,
+    errors: &RefCell<Vec<String>>,
+    runtime_data: &Rc<RefCell<RuntimeData>>,
+) {
+    errors.borrow_mut().clear();
+
+    if css_path.exists() {
+        info!("Applying custom CSS from {:?}", css_path);
+        provider.load_from_path(css_path);
+    } else {
+        provider.load_from_data("");
+    }
+
+    // Reported once loading is done, so all errors are shown at once. They stay until a reload succeeds
+    let Some(label) = runtime_data.borrow().css_errors.clone() else {
+        return;
+    };
+    let errors = errors.borrow();
+    if !errors.is_empty() {
+        label.set_markup(&format!(
+            r#"<span foreground="red">{}</span>"#,
+            glib::markup_escape_text(&errors.join("\n"))
+        ));
+    }
+    label.set_visible(!errors.is_empty());
+}
@@ -1,8 +1,10 @@
 use std::{cell::RefCell, io, rc::Rc, time::Duration};
 
-use anyrun_interface::{HandleResult, Match};
+use abi_stable::std_types::{ROption, RVec};
+use anyrun_interface::{HandleResult, Match, PluginRef as Plugin, Preview, Prompt};
 use gtk::{
-    gdk::{Key, ModifierType},
+    gdk::{self, Key, ModifierType},
+    gio,
     glib::{self, clone, SourceId},
     prelude::*,
     EventControllerKey,
@@ -11,53 +13,129 @@ use gtk_layer_shell::LayerShell;
 use log::*;
 
 use crate::{
-    config::{style_names, Edge, PostRunAction, RelativeNum, RuntimeData},
+    config::{style_names, CopyContent, Edge, KeyAction, PostRunAction, RelativeNum, RuntimeData},
     gmatch::GMatch,
     handle_post_run_action,
-    plugins::refresh_matches,
+    history::HistoryKey,
+    keybinds::Keybinds,
+    plugins::{queried_plugins, refresh_matches},
     send_command,
+    spawn::spawn,
+    utils::{build_image, build_label, find_monitor},
+    view::{Footer, MatchView, PromptChip},
 };
 
+/// How long informational messages from plugins are shown
+const BANNER_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_PLACEHOLDER: &str = "Search";
+const PROMPT_ICON_SIZE: i32 = 16;
+/// How long the selection has to stay on a match before its preview is asked for, so scrolling
+/// through the list doesn't call into the plugin for every match passed
+const PREVIEW_DELAY: Duration = Duration::from_millis(80);
+/// Amount of rows that get a quick-select badge, like the default Alt+1 to Alt+9 keybinds
+const QUICK_SELECT_BADGES: usize = 9;
+
 pub fn setup_main_window(
     app: &impl IsA<gtk::Application>,
     runtime_data: Rc<RefCell<RuntimeData>>,
 ) -> Rc<gtk::ApplicationWindow> {
-    let width = runtime_data
-        .borrow()
-        .config
-        .width
-        .to_val(runtime_data.borrow().geometry.width().try_into().unwrap());
-    let height = runtime_data
-        .borrow()
-        .config
-        .height
-        .to_val(runtime_data.borrow().geometry.height().try_into().unwrap());
-
-    info!("{} {}", width, height);
-
     let window = Rc::new(
         gtk::ApplicationWindow::builder()
             .application(app)
             .name(style_names::WINDOW)
-            .default_width(width)
-            .default_height(height)
             .build(),
     );
 
     setup_layer_shell(window.clone(), runtime_data.clone());
+    apply_geometry(&*window, &runtime_data.borrow());
+
+    // The size and margins depend on the monitor, which is only known for sure once the window is on one
+    window.connect_realize(clone!(@weak runtime_data => move |window| {
+        let Some(surface) = window.surface() else {
+            return;
+        };
+        surface.connect_enter_monitor(clone!(@weak window, @weak runtime_data => move |_, monitor| {
+            let geometry = monitor.geometry();
+            if runtime_data.borrow().geometry == geometry {
+                return;
+            }
+            debug!("Window entered monitor {:?}", monitor.connector());
+            runtime_data.borrow_mut().geometry = geometry;
+            apply_geometry(&window, &runtime_data.borrow());
+        }));
+    }));
 
     window
 }
 
-fn setup_layer_shell(window: Rc<impl GtkWindowExt>, runtime_data: Rc<RefCell<RuntimeData>>) {
-    window.init_layer_shell();
+/// Keeps the window sized for the monitors that are connected, the daemon outlives docking and undocking
+pub fn watch_monitors(window: Rc<gtk::ApplicationWindow>, runtime_data: Rc<RefCell<RuntimeData>>) {
+    let monitors = window.display().monitors();
 
-    let config = &runtime_data.borrow().config;
-    let geometry = runtime_data.borrow().geometry;
-    let width = geometry.width().try_into().unwrap();
-    let height = geometry.height().try_into().unwrap();
+    let watch_geometry = clone!(@weak window, @weak runtime_data => move |monitor: &gdk::Monitor| {
+        monitor.connect_geometry_notify(clone!(@weak window, @weak runtime_data => move |_| {
+            update_monitor(&window, &runtime_data);
+        }));
+    });
 
-    for (i, edge) in config.edges.clone().into_iter().enumerate() {
+    for monitor in monitors.iter::<gdk::Monitor>().filter_map(|m| m.ok()) {
+        watch_geometry(&monitor);
+    }
+
+    monitors.connect_items_changed(
+        clone!(@weak window, @weak runtime_data => move |monitors, position, _, added| {
+            for i in position..position + added {
+                if let Some(monitor) = monitors.item(i).and_downcast::<gdk::Monitor>() {
+                    watch_geometry(&monitor);
+                }
+            }
+            update_monitor(&window, &runtime_data);
+        }),
+    );
+}
+
+/// Picks the monitor again and resizes the window for it, the change shows up on the next show
+fn update_monitor(window: &gtk::ApplicationWindow, runtime_data: &Rc<RefCell<RuntimeData>>) {
+    let display = window.display();
+    let choice = runtime_data.borrow().config.monitor.clone();
+
+    let geometry = match find_monitor(&display, &choice) {
+        Some(monitor) => {
+            window.set_monitor(&monitor);
+            monitor.geometry()
+        }
+        // Nothing to size against while all monitors are gone, like with the lid closed before docking
+        None if display.monitors().n_items() == 0 => return,
+        None => match window
+            .surface()
+            .and_then(|surface| display.monitor_at_surface(&surface))
+        {
+            Some(monitor) => monitor.geometry(),
+            // A hidden window is on no monitor yet, it is sized once it enters one on the next show
+            None => {
+                debug!("Monitors changed, waiting for the window to enter one");
+                return;
+            }
+        },
+    };
+
+    debug!("Monitors changed, new geometry: {:?}", geometry);
+    runtime_data.borrow_mut().geometry = geometry;
+    apply_geometry(window, &runtime_data.borrow());
+}
+
+/// Sizes the window and sets its margins relative to the geometry of the monitor
+pub fn apply_geometry(window: &impl GtkWindowExt, runtime_data: &RuntimeData) {
+    let config = &runtime_data.config;
+    let width = runtime_data.geometry.width().try_into().unwrap();
+    let height = runtime_data.geometry.height().try_into().unwrap();
+
+    let window_width = config.width.to_val(width);
+    let window_height = config.height.to_val(height);
+    info!("{} {}", window_width, window_height);
+    window.set_default_size(window_width, window_height);
+
+    for (i, edge) in config.edges.iter().enumerate() {
         let margin = config
             .margin
             .get(i)
@@ -66,8 +144,25 @@ fn setup_layer_shell(window: Rc<impl GtkWindowExt>, runtime_data: Rc<RefCell<Run
                 Edge::Left | Edge::Right => width,
                 Edge::Top | Edge::Bottom => height,
             });
-        window.set_anchor(edge.into(), true);
-        window.set_margin(edge.into(), margin);
+        window.set_margin((*edge).into(), margin);
+    }
+
+    if let Some(preview) = &runtime_data.preview {
+        preview.set_width_request(preview_width(runtime_data));
+    }
+}
+
+fn setup_layer_shell(window: Rc<impl GtkWindowExt>, runtime_data: Rc<RefCell<RuntimeData>>) {
+    window.init_layer_shell();
+
+    let config = &runtime_data.borrow().config;
+
+    for edge in config.edges.iter() {
+        window.set_anchor((*edge).into(), true);
+    }
+
+    if let Some(monitor) = find_monitor(&window.display(), &config.monitor) {
+        window.set_monitor(&monitor);
     }
 
     window.set_namespace("anyrun");
@@ -85,19 +180,15 @@ fn setup_layer_shell(window: Rc<impl GtkWindowExt>, runtime_data: Rc<RefCell<Run
     window.set_layer(config.layer.into());
 }
 
-pub fn setup_entry(
-    runtime_data: Rc<RefCell<RuntimeData>>,
-) -> (Rc<gtk::SearchEntry>, EventControllerKey) {
+pub fn setup_entry(runtime_data: Rc<RefCell<RuntimeData>>) -> Rc<gtk::SearchEntry> {
     let entry = Rc::new(
         gtk::SearchEntry::builder()
             .hexpand(true)
             .name(style_names::ENTRY)
-            .placeholder_text("Search")
+            .placeholder_text(DEFAULT_PLACEHOLDER)
             .build(),
     );
 
-    let entry_eck = gtk::EventControllerKey::new();
-
     let debounce_timeout: Rc<RefCell<Option<SourceId>>> = Rc::new(RefCell::new(None));
     entry.connect_changed(clone!(@strong debounce_timeout => move |e| {
         if let Some(timeout_id) = debounce_timeout.borrow_mut().take() {
@@ -105,6 +196,7 @@ pub fn setup_entry(
         }
 
         runtime_data.borrow_mut().exclusive = None;
+        hide_banner(&mut runtime_data.borrow_mut());
         *debounce_timeout.borrow_mut() = Some(glib::timeout_add_local_once(
             Duration::from_millis(runtime_data.borrow().config.smooth_input_time),
             clone!(@weak e, @weak runtime_data, @strong debounce_timeout => move || {
@@ -114,41 +206,225 @@ pub fn setup_entry(
         ));
     }));
 
-    (entry, entry_eck)
+    entry
 }
 
 pub fn setup_activation(
     entry: Rc<gtk::SearchEntry>,
-    main_list: Rc<gtk::ListBox>,
+    main_list: Rc<MatchView>,
+    runtime_data: Rc<RefCell<RuntimeData>>,
+) {
+    entry.connect_activate(clone!(@strong main_list, @weak runtime_data =>
+        move |e| {
+        if let Some(row_id) = main_list.selected_index() {
+            handle_selection_activation(row_id, e, None, runtime_data.clone());
+        }
+    }));
+
+    main_list.connect_activated(clone!(@strong entry, @weak runtime_data =>
+        move |row_id| {
+        handle_selection_activation(row_id, &entry, None, runtime_data.clone());
+    }));
+}
+
+/// Opens the action menu of a match on a right click, the keyboard opens it through the keybinds
+pub fn setup_action_menu(
+    entry: Rc<gtk::SearchEntry>,
+    main_list: Rc<MatchView>,
     runtime_data: Rc<RefCell<RuntimeData>>,
 ) {
-    entry.connect_activate(
-        clone!(@strong main_list, @weak runtime_data, @strong entry =>
-            move |e| {
-            if let Some(row) = main_list.selected_row() {
-                handle_selection_activation(
-                    row.index().try_into().unwrap(),
-                    runtime_data.clone(),
-                    |_| refresh_matches(&e.text(), runtime_data.clone()),
-                );
-                entry.delete_text(0, -1);
+    let click = gtk::GestureClick::builder()
+        .button(gdk::BUTTON_SECONDARY)
+        .build();
+    click.connect_pressed(
+        clone!(@weak main_list, @strong entry, @weak runtime_data => move |_, _, x, y| {
+            if let Some(row_id) = main_list.index_at(x, y) {
+                main_list.select(row_id);
+                show_action_menu(row_id, &main_list, entry.clone(), runtime_data.clone());
             }
         }),
     );
+    main_list.widget().add_controller(click);
+}
 
-    main_list.connect_row_activated(clone!(@strong entry, @weak runtime_data =>
-        move |_, row| {
+/// Shows the actions of the match in a popover under its row
+fn show_action_menu(
+    row_id: usize,
+    main_list: &MatchView,
+    entry: Rc<gtk::SearchEntry>,
+    runtime_data: Rc<RefCell<RuntimeData>>,
+) {
+    let Some(row) = main_list.item_widget(row_id) else {
+        return;
+    };
+    let actions = match runtime_data.borrow().list_store.item(row_id as u32) {
+        Some(item) => item
+            .downcast::<GMatch>()
+            .expect("Failed to downcast Object to MatchRow")
+            .get_actions(),
+        None => return,
+    };
+
+    if actions.is_empty() {
+        return;
+    }
+
+    let action_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::Browse)
+        .name(style_names::ACTIONS)
+        .build();
+
+    let icon_size = runtime_data.borrow().config.match_icon_size;
+    for action in &actions {
+        let hbox = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(8)
+            .build();
+        if let ROption::RSome(icon) = &action.icon {
+            hbox.append(&build_image(icon, icon_size));
+        }
+        hbox.append(&build_label(
+            style_names::ACTION_TITLE,
+            false,
+            &action.title,
+        ));
+        action_list.append(&hbox);
+    }
+    action_list.select_row(action_list.row_at_index(0).as_ref());
+
+    let popover = gtk::Popover::builder().child(&action_list).build();
+    popover.set_parent(&row);
+    popover.connect_closed(|popover| {
+        // The popover can't be unparented while it is still being closed
+        glib::idle_add_local_once(clone!(@weak popover => move || popover.unparent()));
+    });
+
+    action_list.connect_row_activated(clone!(@weak popover, @weak runtime_data =>
+        move |_, action_row| {
+        popover.popdown();
         handle_selection_activation(
-            row.index().try_into().unwrap(),
+            row_id,
+            &entry,
+            Some(actions[action_row.index() as usize].id),
             runtime_data.clone(),
-            |_| refresh_matches(&entry.text(), runtime_data.clone()),
         );
-        entry.delete_text(0, -1);
     }));
+
+    popover.popup();
+}
+
+/// Builds the preview pane, which shows what the plugin provides for the selected match
+pub fn setup_preview(
+    main_list: Rc<MatchView>,
+    runtime_data: Rc<RefCell<RuntimeData>>,
+) -> Option<gtk::ScrolledWindow> {
+    if !runtime_data.borrow().config.show_preview {
+        return None;
+    }
+
+    let preview_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .name(style_names::PREVIEW)
+        .build();
+
+    let preview = gtk::ScrolledWindow::builder()
+        .vexpand(true)
+        .focusable(false)
+        .width_request(preview_width(&runtime_data.borrow()))
+        .visible(false)
+        .child(&preview_box)
+        .build();
+
+    let delay_timeout: Rc<RefCell<Option<SourceId>>> = Rc::new(RefCell::new(None));
+    main_list.connect_selected(
+        clone!(@weak preview, @weak preview_box, @weak runtime_data => move |row_id| {
+            if let Some(timeout_id) = delay_timeout.borrow_mut().take() {
+                timeout_id.remove();
+            }
+
+            *delay_timeout.borrow_mut() = Some(glib::timeout_add_local_once(
+                PREVIEW_DELAY,
+                clone!(@weak preview, @weak preview_box, @weak runtime_data, @strong delay_timeout => move || {
+                    *delay_timeout.borrow_mut() = None;
+                    while let Some(child) = preview_box.first_child() {
+                        preview_box.remove(&child);
+                    }
+
+                    match row_id.and_then(|row_id| fetch_preview(row_id, &runtime_data)) {
+                        Some(content) => {
+                            preview_box.append(&build_preview(content));
+                            preview.set_visible(true);
+                        }
+                        None => preview.set_visible(false),
+                    }
+                }),
+            ));
+        }),
+    );
+
+    runtime_data.borrow_mut().preview = Some(preview.clone());
+    Some(preview)
+}
+
+/// Width of the preview pane for the monitor the window is on
+fn preview_width(runtime_data: &RuntimeData) -> i32 {
+    let runner_width = runtime_data
+        .config
+        .width
+        .to_val(runtime_data.geometry.width().try_into().unwrap());
+    runtime_data
+        .config
+        .preview_width
+        .to_val(runner_width.try_into().unwrap_or_default())
+}
+
+fn fetch_preview(row_id: usize, runtime_data: &Rc<RefCell<RuntimeData>>) -> Option<Preview> {
+    let gmatch = runtime_data
+        .borrow()
+        .list_store
+        .item(row_id.try_into().ok()?)?
+        .downcast::<GMatch>()
+        .expect("Failed to downcast Object to MatchRow");
+
+    let plugin = *runtime_data
+        .borrow()
+        .plugins
+        .get(gmatch.get_plugin_id() as usize)?;
+
+    plugin.preview()?(gmatch.into()).into_option()
+}
+
+fn build_preview(content: Preview) -> gtk::Widget {
+    match content {
+        Preview::Pango(text) => gtk::Label::builder()
+            .label(text.as_str())
+            .use_markup(true)
+            .wrap(true)
+            .selectable(true)
+            .xalign(0.0)
+            .valign(gtk::Align::Start)
+            .build()
+            .upcast(),
+        Preview::Monospace(text) => gtk::Label::builder()
+            .label(text.as_str())
+            .css_classes(["monospace"])
+            .selectable(true)
+            .xalign(0.0)
+            .valign(gtk::Align::Start)
+            .build()
+            .upcast(),
+        Preview::Image(path) => gtk::Picture::builder()
+            .file(&gio::File::for_path(path.as_str()))
+            .can_shrink(true)
+            .keep_aspect_ratio(true)
+            .vexpand(true)
+            .build()
+            .upcast(),
+    }
 }
 
 fn connect_key_press_events<F>(
-    widget: Rc<impl WidgetExt>,
+    widget: &impl WidgetExt,
     event_controller_key: gtk::EventControllerKey,
     handler: F,
 ) where
@@ -159,103 +435,191 @@ fn connect_key_press_events<F>(
         .connect_key_pressed(move |ctrl, keyval, _, mods| handler(ctrl, keyval, mods));
 }
 
+/// The completion of the selected match, if it has one
+fn selected_completion(
+    main_list: &MatchView,
+    runtime_data: &Rc<RefCell<RuntimeData>>,
+) -> Option<String> {
+    let row_id = main_list.selected_index()?;
+    runtime_data
+        .borrow()
+        .list_store
+        .item(row_id.try_into().ok()?)?
+        .downcast::<GMatch>()
+        .ok()?
+        .get_completion()
+}
+
 pub fn connect_entry_with_window_key_press_events(
-    entry_widget: Rc<impl EditableExt + WidgetExt>,
-    entry_ec_key: gtk::EventControllerKey,
-    listview: &Rc<gtk::ListBox>,
+    entry_widget: Rc<gtk::SearchEntry>,
+    listview: &Rc<MatchView>,
     listview_ec_key: gtk::EventControllerKey,
+    runtime_data: Rc<RefCell<RuntimeData>>,
 ) {
+    let keybinds = Rc::new(Keybinds::new(&runtime_data.borrow().config.keybinds));
+
+    // The entry gets them before its own handling, like activating on Return. The list gets them
+    // after its own, so the action menu popover under its rows keeps its keys.
+    for (widget, phase) in [
+        (
+            entry_widget.upcast_ref::<gtk::Widget>(),
+            gtk::PropagationPhase::Capture,
+        ),
+        (listview.widget(), gtk::PropagationPhase::Bubble),
+    ] {
+        let controller = gtk::EventControllerKey::new();
+        controller.set_propagation_phase(phase);
+        controller.connect_key_pressed(
+            clone!(@strong keybinds, @strong entry_widget, @strong listview, @weak runtime_data => @default-return glib::Propagation::Proceed,
+                move |_, keyval, keycode, mods| {
+                match keybinds.action(&entry_widget.display(), keyval, keycode, mods) {
+                    // The arrow keys move through the grid by rows, like the grid does on its own
+                    Some(KeyAction::Next | KeyAction::Prev)
+                        if listview.is_grid() && matches!(keyval, Key::Up | Key::Down) =>
+                    {
+                        let count = if keyval == Key::Down { 1 } else { -1 };
+                        listview.move_cursor(gtk::MovementStep::DisplayLines, count);
+                        glib::Propagation::Stop
+                    }
+                    Some(action) => run_key_action(action, &entry_widget, &listview, &runtime_data),
+                    None => glib::Propagation::Proceed,
+                }
+            }),
+        );
+        widget.add_controller(controller);
+    }
+
     let ent = entry_widget.clone();
-    let lv = listview.clone();
     connect_key_press_events(
-        lv.clone(),
+        listview.widget(),
         listview_ec_key,
-        move |ctrl, keyval, mods| match keyval {
-            Key::Escape => {
-                send_command("hide");
-                ent.delete_text(0, -1);
-                glib::Propagation::Stop
-            }
-            Key::j | Key::J => {
-                if mods.contains(ModifierType::CONTROL_MASK) {
-                    lv.emit_move_cursor(gtk::MovementStep::DisplayLines, 1, false, false);
-                    return glib::Propagation::Stop;
+        clone!(@strong listview => move |ctrl, keyval, _| {
+            match keyval {
+                Key::Control_L | Key::Control_R | Key::Up | Key::Down | Key::Return => {
+                    glib::Propagation::Proceed
                 }
-                ctrl.forward(ent.upcast_ref::<gtk::Widget>());
-                ent.grab_focus();
-                glib::Propagation::Stop
-            }
-            Key::k | Key::K => {
-                if mods.contains(ModifierType::CONTROL_MASK) {
-                    lv.emit_move_cursor(gtk::MovementStep::DisplayLines, -1, false, false);
-                    return glib::Propagation::Stop;
+                // The layout changes with the plugins a query goes to
+                Key::Left | Key::Right if listview.is_grid() => glib::Propagation::Proceed,
+                // Typing while the list has focus goes to the entry
+                _ => {
+                    ctrl.forward(ent.upcast_ref::<gtk::Widget>());
+                    ent.grab_focus();
+                    glib::Propagation::Proceed
                 }
-                ctrl.forward(ent.upcast_ref::<gtk::Widget>());
-                ent.grab_focus();
-                glib::Propagation::Stop
-            }
-            Key::Control_L | Key::Control_R | Key::Up | Key::Down | Key::Return => {
-                glib::Propagation::Proceed
             }
-            _ => {
-                ctrl.forward(ent.upcast_ref::<gtk::Widget>());
-                ent.grab_focus();
-                glib::Propagation::Proceed
-            }
-        },
+        }),
     );
+}
 
-    let ent = entry_widget.clone();
-    let lv = listview.clone();
-    connect_key_press_events(
-        ent.clone(),
-        entry_ec_key,
-        move |ctrl, keyval, mods| match keyval {
-            Key::Escape => {
-                send_command("hide");
-                ent.delete_text(0, -1);
-                glib::Propagation::Stop
+fn run_key_action(
+    action: KeyAction,
+    entry: &Rc<gtk::SearchEntry>,
+    main_list: &MatchView,
+    runtime_data: &Rc<RefCell<RuntimeData>>,
+) -> glib::Propagation {
+    match action {
+        KeyAction::Next => main_list.move_selection(1),
+        KeyAction::Prev => main_list.move_selection(-1),
+        KeyAction::PageDown => main_list.move_cursor(gtk::MovementStep::Pages, 1),
+        KeyAction::PageUp => main_list.move_cursor(gtk::MovementStep::Pages, -1),
+        KeyAction::First => main_list.move_cursor(gtk::MovementStep::BufferEnds, -1),
+        KeyAction::Last => main_list.move_cursor(gtk::MovementStep::BufferEnds, 1),
+        KeyAction::Activate => {
+            if let Some(row_id) = main_list.selected_index() {
+                handle_selection_activation(row_id, entry, None, runtime_data.clone());
             }
-            Key::Down | Key::J | Key::j => {
-                if (mods.contains(ModifierType::CONTROL_MASK)
-                    && (keyval == Key::J || keyval == Key::j))
-                    || keyval == Key::Down
-                {
-                    if let Some(row) = lv.selected_row() {
-                        row.grab_focus();
-                    }
-                    ctrl.forward(lv.upcast_ref::<gtk::Widget>());
-
-                    return glib::Propagation::Stop;
-                }
-                glib::Propagation::Proceed
+        }
+        KeyAction::SecondaryAction => {
+            if let Some(row_id) = main_list.selected_index() {
+                show_action_menu(row_id, main_list, entry.clone(), runtime_data.clone());
             }
-            Key::Up | Key::K | Key::k => {
-                if (mods.contains(ModifierType::CONTROL_MASK)
-                    && (keyval == Key::K || keyval == Key::k))
-                    || keyval == Key::Up
-                {
-                    if let Some(row) = lv.selected_row() {
-                        row.grab_focus();
-                    }
-                    ctrl.forward(lv.upcast_ref::<gtk::Widget>());
-                    return glib::Propagation::Stop;
-                }
-                glib::Propagation::Proceed
+        }
+        KeyAction::Complete => match selected_completion(main_list, runtime_data) {
+            Some(completion) => {
+                entry.set_text(&completion);
+                entry.grab_focus();
+                entry.set_position(-1);
+            }
+            None => {
+                return run_key_action(KeyAction::SecondaryAction, entry, main_list, runtime_data)
             }
-            Key::Control_L | Key::Control_R => glib::Propagation::Proceed,
-            _ => glib::Propagation::Proceed,
         },
-    );
+        KeyAction::ToggleMark => {
+            if !multi_select_enabled(&runtime_data.borrow()) {
+                return glib::Propagation::Proceed;
+            }
+            if let Some(row_id) = main_list.selected_index() {
+                toggle_mark(row_id, runtime_data);
+            }
+        }
+        KeyAction::ActivateNth(n) => {
+            let Some(offset) = (n as usize).checked_sub(1) else {
+                return glib::Propagation::Proceed;
+            };
+            let row_id = main_list.first_visible_index().unwrap_or(0) + offset;
+            if row_id >= runtime_data.borrow().list_store.n_items() as usize {
+                return glib::Propagation::Proceed;
+            }
+            handle_selection_activation(row_id, entry, None, runtime_data.clone());
+        }
+        KeyAction::ClearQuery => {
+            entry.delete_text(0, -1);
+            entry.grab_focus();
+        }
+        KeyAction::Close => {
+            runtime_data.borrow_mut().pinned = None;
+            send_command("hide");
+            entry.delete_text(0, -1);
+        }
+        KeyAction::TogglePlugin => {
+            let pinned = if runtime_data.borrow().pinned.is_some() {
+                None
+            } else {
+                match selected_plugin(main_list, runtime_data) {
+                    Some(plugin) => Some(plugin),
+                    None => return glib::Propagation::Stop,
+                }
+            };
+            runtime_data.borrow_mut().pinned = pinned;
+            refresh_matches(&entry.text(), runtime_data.clone());
+        }
+    }
+    glib::Propagation::Stop
+}
+
+/// The plugin of the selected match
+fn selected_plugin(
+    main_list: &MatchView,
+    runtime_data: &Rc<RefCell<RuntimeData>>,
+) -> Option<Plugin> {
+    let row_id = main_list.selected_index()?;
+    let gmatch = runtime_data
+        .borrow()
+        .list_store
+        .item(row_id.try_into().ok()?)?
+        .downcast::<GMatch>()
+        .ok()?;
+    runtime_data
+        .borrow()
+        .plugins
+        .get(gmatch.get_plugin_id() as usize)
+        .copied()
 }
 
-fn handle_selection_activation<F>(
+fn handle_selection_activation(
     row_id: usize,
+    entry: &gtk::SearchEntry,
+    action: Option<u64>,
     runtime_data: Rc<RefCell<RuntimeData>>,
-    mut on_refresh: F,
-) where
-    F: FnMut(bool),
-{
+) {
+    let query = entry.text();
+
+    let marked = marked_matches(&runtime_data.borrow());
+    if action.is_none() && !marked.is_empty() {
+        handle_marked_activation(marked, entry, runtime_data);
+        return;
+    }
+
     let gmatch = runtime_data
         .borrow()
         .list_store
@@ -271,32 +635,440 @@ fn handle_selection_activation<F>(
         .get(gmatch.get_plugin_id() as usize)
         .expect("Can't get plugin");
 
-    match plugin.handle_selection()(rmatch) {
-        HandleResult::Close => send_command("hide"),
-        HandleResult::Refresh(exclusive) => {
-            runtime_data.borrow_mut().exclusive = if exclusive { Some(plugin) } else { None };
-            on_refresh(exclusive);
+    let history_key = HistoryKey::new(&plugin.info()().name, &rmatch);
+    runtime_data
+        .borrow_mut()
+        .history
+        .record(history_key, &query);
+
+    let result = plugin.handle_selection()(rmatch, action.into());
+    apply_results(vec![(plugin, result)], entry, runtime_data);
+}
+
+/// Hands the marked matches to their plugins, all at once for plugins that support it
+fn handle_marked_activation(
+    marked: Vec<GMatch>,
+    entry: &gtk::SearchEntry,
+    runtime_data: Rc<RefCell<RuntimeData>>,
+) {
+    let query = entry.text();
+
+    // Grouped by plugin, in the order the plugins first appear in the list
+    let mut groups: Vec<(u64, Vec<Match>)> = Vec::new();
+    for gmatch in marked {
+        gmatch.set_marked(false);
+        let plugin_id = gmatch.get_plugin_id();
+        let rmatch: Match = gmatch.into();
+        match groups.iter_mut().find(|(id, _)| *id == plugin_id) {
+            Some((_, rmatches)) => rmatches.push(rmatch),
+            None => groups.push((plugin_id, vec![rmatch])),
         }
-        HandleResult::Copy(bytes) => {
-            send_command("hide");
-            runtime_data.borrow_mut().post_run_action = PostRunAction::Copy(bytes.into());
+    }
+
+    let mut results = Vec::new();
+    for (plugin_id, rmatches) in groups {
+        let plugin = *runtime_data
+            .borrow()
+            .plugins
+            .get(plugin_id as usize)
+            .expect("Can't get plugin");
+
+        let name = plugin.info()().name;
+        for rmatch in &rmatches {
+            let history_key = HistoryKey::new(&name, rmatch);
+            runtime_data
+                .borrow_mut()
+                .history
+                .record(history_key, &query);
+        }
+
+        match handle_selections(&plugin) {
+            Some(handle_selections) => results.push((plugin, handle_selections(rmatches.into()))),
+            None => results.extend(
+                rmatches
+                    .into_iter()
+                    .map(|rmatch| (plugin, plugin.handle_selection()(rmatch, ROption::RNone))),
+            ),
         }
-        HandleResult::Stdout(bytes) => {
-            if let Err(why) = io::Write::write_all(&mut io::stdout().lock(), &bytes) {
-                error!("Error outputting content to stdout: {}", why);
+    }
+
+    apply_results(results, entry, runtime_data);
+}
+
+/// Acts on what the plugins returned. Closing wins over refreshing or replacing the query, copied content
+/// is joined and every output to stdout ends up on its own line when there are several results.
+fn apply_results(
+    results: Vec<(Plugin, HandleResult)>,
+    entry: &gtk::SearchEntry,
+    runtime_data: Rc<RefCell<RuntimeData>>,
+) {
+    let separate = results.len() > 1;
+    let mut close = false;
+    let mut refresh = None;
+    let mut query = None;
+    let mut message = None;
+    let mut copy: Option<CopyContent> = None;
+
+    for (plugin, result) in results {
+        match result {
+            HandleResult::Close => close = true,
+            HandleResult::Refresh(exclusive) => {
+                refresh = Some(if exclusive { Some(plugin) } else { None })
+            }
+            HandleResult::Copy(bytes) => match &mut copy {
+                // Plain copies of several matches are joined line by line
+                Some(CopyContent {
+                    bytes: copied,
+                    mime_type: None,
+                    primary: false,
+                }) => {
+                    copied.push(b'\n');
+                    copied.extend_from_slice(&bytes);
+                }
+                _ => {
+                    copy = Some(CopyContent {
+                        bytes: bytes.into(),
+                        mime_type: None,
+                        primary: false,
+                    })
+                }
+            },
+            HandleResult::CopyAs(request) => {
+                copy = Some(CopyContent {
+                    bytes: request.bytes.into(),
+                    mime_type: request.mime_type.into_option().map(Into::into),
+                    primary: request.primary,
+                })
+            }
+            HandleResult::Stdout(mut bytes) => {
+                if separate && !bytes.ends_with(b"\n") {
+                    bytes.push(b'\n');
+                }
+                if let Err(why) = io::Write::write_all(&mut io::stdout().lock(), &bytes) {
+                    error!("Error outputting content to stdout: {}", why);
+                }
+                close = true;
+            }
+            HandleResult::Query(update) => query = Some((plugin, update)),
+            HandleResult::Spawn(request) => match spawn(request, &runtime_data.borrow().config) {
+                Ok(()) => close = true,
+                Err(why) => message = Some((why.into(), true)),
+            },
+            HandleResult::Info(text) => {
+                // An error from another plugin is more important
+                if !matches!(message, Some((_, true))) {
+                    message = Some((text, false));
+                }
+            }
+            HandleResult::Error(text) => message = Some((text, true)),
+        }
+    }
+
+    if copy.is_some() {
+        close = true;
+    }
+
+    // The window stays open with the query untouched when something went wrong
+    let failed = matches!(message, Some((_, true)));
+    let closing = close && !failed;
+
+    if closing {
+        // Copied content is only handed out when the window actually closes
+        if let Some(content) = copy {
+            runtime_data.borrow_mut().post_run_action = PostRunAction::Copy(content);
+        }
+        send_command("hide");
+        entry.delete_text(0, -1);
+    } else if let Some((plugin, update)) = query {
+        let unchanged = entry.text() == update.text.as_str();
+        entry.set_text(&update.text);
+        entry.set_position(update.cursor.map_or(-1, |cursor| cursor as i32));
+        // Editing the entry drops the exclusive mode, so it is set again afterwards
+        runtime_data.borrow_mut().exclusive = if update.exclusive { Some(plugin) } else { None };
+        if unchanged {
+            refresh_matches(&entry.text(), runtime_data.clone());
+        }
+    } else if !failed {
+        if let Some(exclusive) = refresh {
+            runtime_data.borrow_mut().exclusive = exclusive;
+            refresh_matches(&entry.text(), runtime_data.clone());
+        }
+        entry.delete_text(0, -1);
+    }
+
+    // Shown last, as editing the entry hides the banner. Nobody would see it on a closing window
+    if let Some((text, error)) = message {
+        if closing {
+            info!("Message from plugin: {}", text);
+        } else {
+            show_banner(&runtime_data, &text, error);
+        }
+    }
+}
+
+/// Shows the prompt of the plugin the query goes to, when it goes to a single one.
+/// Only the plugins taking part in the query are asked, the first one in the list order wins.
+pub fn update_prompt(input: &str, runtime_data: &Rc<RefCell<RuntimeData>>) {
+    if runtime_data.borrow().prompt.is_none() {
+        return;
+    }
+
+    let (exclusive, plugins) = {
+        let rt_data = runtime_data.borrow();
+        let mut plugins = queried_plugins(&rt_data);
+        plugins.sort_by_key(|(plugin_id, _)| rt_data.plugin_ranks[*plugin_id]);
+        (rt_data.exclusive.or(rt_data.pinned), plugins)
+    };
+
+    let prompt = plugins
+        .iter()
+        .find_map(|(_, plugin)| {
+            plugin.prompt()?(input.into())
+                .into_option()
+                .map(|prompt| (*plugin, Some(prompt)))
+        })
+        // An exclusive plugin without a prompt of its own is shown by name
+        .or(exclusive.map(|plugin| (plugin, None)));
+
+    if let Some(chip) = &runtime_data.borrow().prompt {
+        show_prompt(chip, prompt);
+    }
+}
+
+fn show_prompt(chip: &PromptChip, prompt: Option<(Plugin, Option<Prompt>)>) {
+    while let Some(child) = chip.container.first_child() {
+        chip.container.remove(&child);
+    }
+
+    match prompt {
+        Some((plugin, prompt)) => {
+            let info = plugin.info()();
+            chip.container
+                .append(&build_image(&info.icon, PROMPT_ICON_SIZE));
+
+            let (text, placeholder) = match prompt {
+                Some(prompt) => (prompt.text.into_string(), prompt.placeholder.into_option()),
+                None => (info.name.into_string(), None),
+            };
+            chip.container.append(&gtk::Label::new(Some(&text)));
+            chip.entry
+                .set_placeholder_text(Some(placeholder.as_deref().unwrap_or(DEFAULT_PLACEHOLDER)));
+            chip.container.set_visible(true);
+        }
+        None => {
+            chip.entry.set_placeholder_text(Some(DEFAULT_PLACEHOLDER));
+            chip.container.set_visible(false);
+        }
+    }
+}
+
+/// Shows how many results there are, which plugins are still searching and how long the query took
+pub fn update_footer(runtime_data: &Rc<RefCell<RuntimeData>>) {
+    let rt_data = runtime_data.borrow();
+    if let Some(footer) = &rt_data.footer {
+        show_footer(footer, &rt_data);
+    }
+}
+
+fn show_footer(footer: &Footer, rt_data: &RuntimeData) {
+    if !rt_data.refresh.is_started() {
+        footer.spinner.set_spinning(false);
+        footer.spinner.set_visible(false);
+        footer.label.set_text("");
+        return;
+    }
+
+    let count = rt_data.refresh.count();
+    let mut status = vec![if count == 1 {
+        "1 result".to_string()
+    } else {
+        format!("{} results", count)
+    }];
+
+    let pending: Vec<String> = rt_data
+        .refresh
+        .pending()
+        .map(|plugin_id| rt_data.plugins[plugin_id].info()().name.into_string())
+        .collect();
+    if !pending.is_empty() {
+        status.push(format!("waiting for {}", pending.join(", ")));
+    } else if let Some(took) = rt_data.refresh.took() {
+        status.push(format!("{} ms", took.as_millis()));
+    }
+
+    footer.spinner.set_spinning(!pending.is_empty());
+    footer.spinner.set_visible(!pending.is_empty());
+    footer.label.set_text(&status.join(" · "));
+}
+
+/// Shows a message from a plugin above the list. Errors stay until the query is edited,
+/// other messages disappear after a few seconds.
+fn show_banner(runtime_data: &Rc<RefCell<RuntimeData>>, message: &str, error: bool) {
+    let mut rt_data = runtime_data.borrow_mut();
+    hide_banner(&mut rt_data);
+
+    let Some(banner) = rt_data.banner.clone() else {
+        return;
+    };
+
+    let message = glib::markup_escape_text(message);
+    banner.set_markup(&if error {
+        format!(r#"<span foreground="red">{}</span>"#, message)
+    } else {
+        message.to_string()
+    });
+    banner.set_visible(true);
+
+    if !error {
+        rt_data.banner_timeout = Some(glib::timeout_add_local_once(
+            BANNER_TIMEOUT,
+            clone!(@weak runtime_data => move || {
+                let mut rt_data = runtime_data.borrow_mut();
+                // The source is gone once it has run, it must not be removed again
+                rt_data.banner_timeout = None;
+                hide_banner(&mut rt_data);
+            }),
+        ));
+    }
+}
+
+fn hide_banner(runtime_data: &mut RuntimeData) {
+    if let Some(timeout_id) = runtime_data.banner_timeout.take() {
+        timeout_id.remove();
+    }
+    if let Some(banner) = &runtime_data.banner {
+        banner.set_visible(false);
+    }
+}
+
+/// Multi-select is on when asked for in the config, or when all plugins being queried can handle marked matches at once
+fn multi_select_enabled(runtime_data: &RuntimeData) -> bool {
+    runtime_data.config.multi_select
+        || match runtime_data.exclusive.or(runtime_data.pinned) {
+            Some(plugin) => handle_selections(&plugin).is_some(),
+            None => {
+                !runtime_data.plugins.is_empty()
+                    && runtime_data
+                        .plugins
+                        .iter()
+                        .all(|plugin| handle_selections(plugin).is_some())
             }
-            send_command("hide");
         }
+}
+
+/// The function handling all marked matches at once, if the plugin has one
+fn handle_selections(plugin: &Plugin) -> Option<extern "C" fn(RVec<Match>) -> HandleResult> {
+    plugin.handle_selections()?.into_option()
+}
+
+/// The marked matches in list order
+fn marked_matches(runtime_data: &RuntimeData) -> Vec<GMatch> {
+    runtime_data
+        .list_store
+        .iter::<GMatch>()
+        .filter_map(Result::ok)
+        .filter(|gmatch| gmatch.get_marked())
+        .collect()
+}
+
+fn toggle_mark(row_id: usize, runtime_data: &Rc<RefCell<RuntimeData>>) {
+    if !multi_select_enabled(&runtime_data.borrow()) {
+        return;
+    }
+
+    let gmatch = runtime_data
+        .borrow()
+        .list_store
+        .item(row_id.try_into().unwrap())
+        .and_then(|item| item.downcast::<GMatch>().ok());
+
+    if let Some(gmatch) = gmatch {
+        gmatch.set_marked(!gmatch.get_marked());
     }
 }
 
+/// Marks rows for multi-select with a Shift+click, the keyboard marks them through the keybinds
+pub fn setup_multi_select(main_list: Rc<MatchView>, runtime_data: Rc<RefCell<RuntimeData>>) {
+    let click = gtk::GestureClick::builder()
+        .button(gdk::BUTTON_PRIMARY)
+        .propagation_phase(gtk::PropagationPhase::Capture)
+        .build();
+    click.connect_pressed(
+        clone!(@weak main_list, @weak runtime_data => move |gesture, _, x, y| {
+            if !gesture.current_event_state().contains(ModifierType::SHIFT_MASK) {
+                return;
+            }
+            if let Some(row_id) = main_list.index_at(x, y) {
+                // Keeps the list from activating the row
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                main_list.select(row_id);
+                toggle_mark(row_id, &runtime_data);
+            }
+        }),
+    );
+    main_list.widget().add_controller(click);
+}
+
+/// Keeps the numbers of the quick-select badges in sync with the rows at the top of the scrolled area.
+/// Needs the list to be in the window already.
+pub fn setup_quick_select(main_list: Rc<MatchView>, runtime_data: Rc<RefCell<RuntimeData>>) {
+    if !runtime_data.borrow().config.quick_select_badges {
+        return;
+    }
+    let Some(adjustment) = main_list.vadjustment() else {
+        return;
+    };
+
+    // The matches that show a badge right now, so only the ones entering or leaving that spot change
+    let badged: Rc<RefCell<Vec<GMatch>>> = Rc::new(RefCell::new(Vec::new()));
+    let update = Rc::new(
+        clone!(@weak main_list, @weak runtime_data, @strong badged => move || {
+            update_quick_indices(&main_list, &runtime_data, &mut badged.borrow_mut());
+        }),
+    );
+
+    // The adjustment changes once the rows are laid out, so the positions are up to date by then
+    adjustment.connect_value_changed(clone!(@strong update => move |_| update()));
+    adjustment.connect_changed(clone!(@strong update => move |_| update()));
+    let list_store = runtime_data.borrow().list_store.clone();
+    list_store.connect_items_changed(move |_, _, _, _| {
+        glib::idle_add_local_once(clone!(@strong update => move || update()));
+    });
+}
+
+fn update_quick_indices(
+    main_list: &MatchView,
+    runtime_data: &Rc<RefCell<RuntimeData>>,
+    badged: &mut Vec<GMatch>,
+) {
+    let first = main_list.first_visible_index().unwrap_or(0);
+    let list_store = runtime_data.borrow().list_store.clone();
+
+    let in_view: Vec<GMatch> = (first..first + QUICK_SELECT_BADGES)
+        .map_while(|index| list_store.item(index as u32).and_downcast::<GMatch>())
+        .collect();
+
+    for gmatch in badged.iter().filter(|gmatch| !in_view.contains(gmatch)) {
+        gmatch.set_quick_index(0);
+    }
+    for (position, gmatch) in in_view.iter().enumerate() {
+        let quick_index = position as u32 + 1;
+        if gmatch.get_quick_index() != quick_index {
+            gmatch.set_quick_index(quick_index);
+        }
+    }
+
+    *badged = in_view;
+}
+
 pub fn configure_main_window(
-    window: Rc<impl WidgetExt + GtkWindowExt + NativeExt>,
+    window: Rc<gtk::ApplicationWindow>,
     runtime_data: Rc<RefCell<RuntimeData>>,
-    entry: Rc<impl WidgetExt>,
-    main_list: Rc<impl WidgetExt>,
+    entry: Rc<gtk::SearchEntry>,
+    main_list: Rc<MatchView>,
+    preview: Option<gtk::ScrolledWindow>,
 ) {
-    let rt_data = runtime_data.borrow_mut();
+    let mut rt_data = runtime_data.borrow_mut();
 
     let main_vbox = gtk::Box::builder()
         .orientation(gtk::Orientation::Vertical)
@@ -322,26 +1094,106 @@ pub fn configure_main_window(
         );
     }
 
+    let banner = gtk::Label::builder()
+        .name(style_names::BANNER)
+        .use_markup(true)
+        .wrap(true)
+        .visible(false)
+        .build();
+    main_vbox.append(&banner);
+    rt_data.banner = Some(banner);
+
+    // Apart from the banner, so messages from plugins don't hide the errors while they are being fixed
+    let css_errors = gtk::Label::builder()
+        .name(style_names::CSS_ERRORS)
+        .use_markup(true)
+        .wrap(true)
+        .visible(false)
+        .build();
+    main_vbox.append(&css_errors);
+    rt_data.css_errors = Some(css_errors);
+    rt_data.window = Some((*window).clone());
+
     let scroll_window = gtk::ScrolledWindow::builder()
         .vexpand(true)
         .hexpand(true)
         .focusable(false)
         .build();
 
-    scroll_window.set_child(Some(&*main_list));
+    scroll_window.set_child(Some(main_list.widget()));
+
+    let results: gtk::Widget = match preview {
+        Some(preview) => {
+            let hbox = gtk::Box::builder()
+                .orientation(gtk::Orientation::Horizontal)
+                .spacing(12)
+                .build();
+            hbox.append(&scroll_window);
+            hbox.append(&preview);
+            hbox.upcast()
+        }
+        None => scroll_window.upcast(),
+    };
+
+    let results: gtk::Widget = if rt_data.config.show_footer {
+        let spinner = gtk::Spinner::builder().visible(false).build();
+        let label = gtk::Label::builder()
+            .xalign(0.0)
+            .ellipsize(gtk::pango::EllipsizeMode::End)
+            .build();
+        let footer = gtk::Box::builder()
+            .orientation(gtk::Orientation::Horizontal)
+            .name(style_names::FOOTER)
+            .spacing(6)
+            .build();
+        footer.append(&spinner);
+        footer.append(&label);
+        rt_data.footer = Some(Footer { spinner, label });
+
+        let vbox = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(6)
+            .build();
+        vbox.append(&results);
+        vbox.append(&footer);
+        vbox.upcast()
+    } else {
+        results
+    };
+
+    let prompt = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .name(style_names::PROMPT)
+        .valign(gtk::Align::Center)
+        .spacing(6)
+        .visible(false)
+        .build();
+    let entry_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    entry_box.append(&prompt);
+    entry_box.append(&*entry);
+    rt_data.prompt = Some(PromptChip {
+        container: prompt,
+        entry: (*entry).clone(),
+    });
 
     if rt_data.config.bottom_entry {
-        main_vbox.append(&scroll_window);
-        main_vbox.append(&*entry);
+        main_vbox.append(&results);
+        main_vbox.append(&entry_box);
     } else {
-        main_vbox.append(&*entry);
-        main_vbox.append(&scroll_window);
+        main_vbox.append(&entry_box);
+        main_vbox.append(&results);
     }
 
     window.set_child(Some(&main_vbox));
     if rt_data.config.daemon {
-        window.connect_hide(clone!(@strong runtime_data =>
-        move |_| handle_post_run_action(&mut runtime_data.borrow_mut().post_run_action, false)));
+        window.connect_hide(clone!(@strong runtime_data => move |_| {
+            let mut rt_data = runtime_data.borrow_mut();
+            handle_post_run_action(&mut rt_data, false);
+            rt_data.history.save_if_changed();
+        }));
     }
     window.connect_show(move |_| {
         entry.grab_focus();
@@ -1,38 +1,103 @@
-use std::{cell::RefCell, fs, path::PathBuf, rc::Rc};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
 
-use gtk::{gdk, gio, prelude::InputStreamExtManual};
+use gtk::{gdk, gio, glib, prelude::*};
 use log::*;
 use nix::{libc, unistd};
 use wl_clipboard_rs::copy;
 
 use crate::{
-    config::{style_names, PostRunAction, RuntimeData},
+    config::{style_names, CopyContent, MonitorChoice, PostRunAction, RuntimeData},
     SOCKET_BUF_SIZE,
 };
 
-fn serve_copy_requests(bytes: &[u8], fork: bool) {
+fn clipboard_type(content: &CopyContent) -> copy::ClipboardType {
+    if content.primary {
+        copy::ClipboardType::Primary
+    } else {
+        copy::ClipboardType::Regular
+    }
+}
+
+fn copy_source(content: &CopyContent) -> (copy::Source, copy::MimeType) {
+    (
+        copy::Source::Bytes(content.bytes.clone().into_boxed_slice()),
+        match &content.mime_type {
+            Some(mime_type) => copy::MimeType::Specific(mime_type.clone()),
+            None => copy::MimeType::Autodetect,
+        },
+    )
+}
+
+fn serve_copy_requests(content: &CopyContent, fork: bool) {
     let mut opts = copy::Options::new();
     opts.foreground(fork);
-    let error = opts
-        .copy(
-            copy::Source::Bytes(bytes.to_vec().into_boxed_slice()),
-            copy::MimeType::Autodetect,
-        )
-        .err();
-    if let Some(why) = error {
+    opts.clipboard(clipboard_type(content));
+    let (source, mime_type) = copy_source(content);
+    if let Err(why) = opts.copy(source, mime_type) {
         error!("Error serving copy requests: {}", why);
     }
 }
 
-pub fn handle_post_run_action(action: &mut PostRunAction, fork: bool) {
-    if let PostRunAction::Copy(bytes) = action {
+/// Serves the content from a thread of the daemon, which must not fork, and takes it off the
+/// clipboard again once the timeout is up, unless something else was copied in the meantime
+fn serve_copy_requests_with_timeout(content: &CopyContent, timeout: u64) {
+    let mut opts = copy::Options::new();
+    opts.clipboard(clipboard_type(content));
+    let (source, mime_type) = copy_source(content);
+    let prepared = match opts.prepare_copy(source, mime_type) {
+        Ok(prepared) => prepared,
+        Err(why) => {
+            error!("Error serving copy requests: {}", why);
+            return;
+        }
+    };
+
+    // Serving ends when the selection is replaced, by something else or by clearing it
+    let replaced = Arc::new(AtomicBool::new(false));
+    let served = replaced.clone();
+    thread::spawn(move || {
+        if let Err(why) = prepared.serve() {
+            error!("Error serving copy requests: {}", why);
+        }
+        served.store(true, Ordering::Relaxed);
+    });
+
+    let clipboard = clipboard_type(content);
+    glib::timeout_add_seconds_local_once(timeout.try_into().unwrap_or(u32::MAX), move || {
+        if replaced.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(why) = copy::clear(clipboard, copy::Seat::All) {
+            error!("Failed to clear the clipboard: {}", why);
+        }
+    });
+}
+
+pub fn handle_post_run_action(runtime_data: &mut RuntimeData, fork: bool) {
+    let timeout = runtime_data.config.copy_timeout;
+    let action = &mut runtime_data.post_run_action;
+
+    if let PostRunAction::Copy(content) = action {
         if fork {
             match unsafe { unistd::fork() } {
                 Ok(unistd::ForkResult::Parent { .. }) => {
                     info!("Child spawned to serve copy requests.");
                 }
                 Ok(unistd::ForkResult::Child) => {
-                    serve_copy_requests(&bytes, fork);
+                    if timeout > 0 {
+                        // SIGALRM ends the child once the time is up
+                        unsafe {
+                            libc::alarm(timeout.try_into().unwrap_or(u32::MAX));
+                        }
+                    }
+                    serve_copy_requests(content, true);
                     // Child should exit after copying?
                     unsafe {
                         libc::_exit(0);
@@ -43,31 +108,15 @@ pub fn handle_post_run_action(action: &mut PostRunAction, fork: bool) {
                 }
             }
         }
-        else {
-            serve_copy_requests(&bytes, fork);
+        else if timeout > 0 {
+            serve_copy_requests_with_timeout(content, timeout);
+        } else {
+            serve_copy_requests(content, fork);
         }
         *action = PostRunAction::None;
     }
 }
 
-pub fn load_custom_css(runtime_data: Rc<RefCell<RuntimeData>>) {
-    let config_dir = &runtime_data.borrow().config_dir;
-    let css_path = config_dir.join("style.css");
-
-    if fs::metadata(&css_path).is_ok() {
-        info!("Applying custom CSS from {:?}", css_path);
-        let provider = gtk::CssProvider::new();
-        provider.load_from_path(css_path);
-
-        let display = gdk::Display::default().expect("Failed to get GDK display for CSS provider!");
-        gtk::style_context_add_provider_for_display(
-            &display,
-            &provider,
-            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
-        );
-    }
-}
-
 pub fn build_label(name: &str, use_markup: bool, label: &str) -> gtk::Label {
     gtk::Label::builder()
         .name(name)
@@ -81,10 +130,10 @@ pub fn build_label(name: &str, use_markup: bool, label: &str) -> gtk::Label {
         .build()
 }
 
-pub fn build_image(icon: &str) -> gtk::Image {
+pub fn build_image(icon: &str, size: i32) -> gtk::Image {
     let mut match_image = gtk::Image::builder()
         .name(style_names::MATCH)
-        .pixel_size(32);
+        .pixel_size(size);
 
     let path = PathBuf::from(icon);
 
@@ -104,3 +153,43 @@ pub fn read_from_stream(stream: &impl InputStreamExtManual) -> String {
         .trim_matches(char::from(0))
         .to_owned()
 }
+
+/// The monitor the window should be bound to, `None` leaves the choice to the compositor
+pub fn find_monitor(display: &gdk::Display, choice: &MonitorChoice) -> Option<gdk::Monitor> {
+    let mut monitors = display
+        .monitors()
+        .into_iter()
+        .filter_map(|m| m.ok()?.downcast::<gdk::Monitor>().ok());
+
+    match choice {
+        MonitorChoice::Focused => None,
+        MonitorChoice::Largest => monitors.max_by_key(|monitor| {
+            let geometry = monitor.geometry();
+            geometry.width() * geometry.height()
+        }),
+        MonitorChoice::Connector(connector) => {
+            let monitor =
+                monitors.find(|monitor| monitor.connector().as_deref() == Some(connector.as_str()));
+            if monitor.is_none() {
+                warn!(
+                    "No monitor connected to {}, using the focused one",
+                    connector
+                );
+            }
+            monitor
+        }
+    }
+}
+
+/// Geometry to size the window against before it is shown on a monitor
+pub fn initial_geometry(display: &gdk::Display, choice: &MonitorChoice) -> gdk::Rectangle {
+    find_monitor(display, choice)
+        .or_else(|| {
+            display
+                .monitors()
+                .into_iter()
+                .find_map(|m| m.ok()?.downcast::<gdk::Monitor>().ok())
+        })
+        .expect("No monitor found")
+        .geometry()
+}
@@ -0,0 +1,229 @@
+use std::{cell::Cell, rc::Rc};
+
+use gtk::{
+    gio,
+    glib::{self, clone},
+    prelude::*,
+};
+
+use crate::config::style_names;
+
+type CreateWidget = dyn Fn(&glib::Object, bool) -> gtk::Widget;
+
+/// The status line under the list
+pub struct Footer {
+    pub spinner: gtk::Spinner,
+    pub label: gtk::Label,
+}
+
+/// Shows in front of the entry which plugin the query goes to
+pub struct PromptChip {
+    pub container: gtk::Box,
+    pub entry: gtk::SearchEntry,
+}
+
+/// The widget showing the matches of the `list_store`, either as a list or as a grid.
+///
+/// Only the layout in use is bound to the model, switching rebuilds the rows in the other one.
+pub struct MatchView {
+    stack: gtk::Stack,
+    list: gtk::ListBox,
+    grid: gtk::FlowBox,
+    is_grid: Cell<bool>,
+    model: gio::ListStore,
+    create_widget: Rc<CreateWidget>,
+}
+
+impl MatchView {
+    /// Builds the view in list mode, `create_widget` gets whether the row is for the grid
+    pub fn new<F>(model: &gio::ListStore, grid_columns: u32, create_widget: F) -> Self
+    where
+        F: Fn(&glib::Object, bool) -> gtk::Widget + 'static,
+    {
+        let list = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::Browse)
+            .name(style_names::MAIN)
+            .build();
+        let grid = gtk::FlowBox::builder()
+            .selection_mode(gtk::SelectionMode::Browse)
+            .homogeneous(true)
+            .max_children_per_line(grid_columns)
+            .valign(gtk::Align::Start)
+            .name(style_names::MAIN)
+            .build();
+
+        // Sized after the layout in use only, the other one is empty anyway
+        let stack = gtk::Stack::builder()
+            .hhomogeneous(false)
+            .vhomogeneous(false)
+            .build();
+        stack.add_child(&list);
+        stack.add_child(&grid);
+
+        let view = Self {
+            stack,
+            list,
+            grid,
+            is_grid: Cell::new(false),
+            model: model.clone(),
+            create_widget: Rc::new(create_widget),
+        };
+        view.bind();
+        view
+    }
+
+    pub fn widget(&self) -> &gtk::Widget {
+        self.stack.upcast_ref()
+    }
+
+    pub fn is_grid(&self) -> bool {
+        self.is_grid.get()
+    }
+
+    /// Switches between the list and the grid, keeping the rows of the model
+    pub fn set_grid(&self, grid: bool) {
+        if self.is_grid.replace(grid) == grid {
+            return;
+        }
+
+        if grid {
+            self.list
+                .bind_model(None::<&gio::ListModel>, |_| unreachable!());
+        } else {
+            self.grid
+                .bind_model(None::<&gio::ListModel>, |_| unreachable!());
+        }
+        self.bind();
+        self.select(0);
+    }
+
+    fn bind(&self) {
+        let create_widget = self.create_widget.clone();
+        if self.is_grid() {
+            self.grid
+                .bind_model(Some(&self.model), move |item| create_widget(item, true));
+            self.stack.set_visible_child(&self.grid);
+        } else {
+            self.list
+                .bind_model(Some(&self.model), move |item| create_widget(item, false));
+            self.stack.set_visible_child(&self.list);
+        }
+    }
+
+    pub fn selected_index(&self) -> Option<usize> {
+        let index = if self.is_grid() {
+            self.grid.selected_children().first()?.index()
+        } else {
+            self.list.selected_row()?.index()
+        };
+        index.try_into().ok()
+    }
+
+    pub fn select(&self, index: usize) {
+        let index = index.try_into().unwrap_or(i32::MAX);
+        if self.is_grid() {
+            if let Some(child) = self.grid.child_at_index(index) {
+                self.grid.select_child(&child);
+            }
+        } else {
+            self.list.select_row(self.list.row_at_index(index).as_ref());
+        }
+    }
+
+    /// The row or grid cell showing the match at `index`
+    pub fn item_widget(&self, index: usize) -> Option<gtk::Widget> {
+        let index = index.try_into().ok()?;
+        if self.is_grid() {
+            self.grid.child_at_index(index).map(|child| child.upcast())
+        } else {
+            self.list.row_at_index(index).map(|row| row.upcast())
+        }
+    }
+
+    /// Index of the match under a point in the coordinates of the view
+    pub fn index_at(&self, x: f64, y: f64) -> Option<usize> {
+        let index = if self.is_grid() {
+            self.grid.child_at_pos(x as i32, y as i32)?.index()
+        } else {
+            self.list.row_at_y(y as i32)?.index()
+        };
+        index.try_into().ok()
+    }
+
+    /// Index of the topmost match in the scrolled area
+    pub fn first_visible_index(&self) -> Option<usize> {
+        let offset = self
+            .vadjustment()
+            .map_or(0.0, |adjustment| adjustment.value());
+        self.index_at(1.0, offset + 1.0)
+    }
+
+    /// The adjustment of the scrolled area the view is in
+    pub fn vadjustment(&self) -> Option<gtk::Adjustment> {
+        self.widget()
+            .parent()
+            .and_downcast::<gtk::Viewport>()?
+            .vadjustment()
+    }
+
+    pub fn move_cursor(&self, step: gtk::MovementStep, count: i32) {
+        // The view moves from the item with focus, which is the entry most of the time
+        if let Some(item) = self
+            .selected_index()
+            .and_then(|index| self.item_widget(index))
+        {
+            item.grab_focus();
+        }
+        if self.is_grid() {
+            self.grid.emit_move_cursor(step, count, false, false);
+        } else {
+            self.list.emit_move_cursor(step, count, false, false);
+        }
+    }
+
+    /// Moves the selection to the next or previous match, in reading order for the grid
+    pub fn move_selection(&self, count: i32) {
+        let step = if self.is_grid() {
+            gtk::MovementStep::VisualPositions
+        } else {
+            gtk::MovementStep::DisplayLines
+        };
+        self.move_cursor(step, count);
+    }
+
+    pub fn connect_selected<F>(&self, callback: F)
+    where
+        F: Fn(Option<usize>) + 'static,
+    {
+        let callback = Rc::new(callback);
+        self.list
+            .connect_row_selected(clone!(@strong callback => move |_, row| {
+                callback(row.and_then(|row| row.index().try_into().ok()))
+            }));
+        self.grid.connect_selected_children_changed(move |grid| {
+            callback(
+                grid.selected_children()
+                    .first()
+                    .and_then(|child| child.index().try_into().ok()),
+            )
+        });
+    }
+
+    pub fn connect_activated<F>(&self, callback: F)
+    where
+        F: Fn(usize) + 'static,
+    {
+        let callback = Rc::new(callback);
+        self.list
+            .connect_row_activated(clone!(@strong callback => move |_, row| {
+                if let Ok(index) = row.index().try_into() {
+                    callback(index)
+                }
+            }));
+        self.grid.connect_child_activated(move |_, child| {
+            if let Ok(index) = child.index().try_into() {
+                callback(index)
+            }
+        });
+    }
+}
@@ -44,8 +44,13 @@ glib-compile-schemas $glib_schemas_dir
 # Create the config directory and the plugins subdirectory
 sudo mkdir -p "${config_dir}/plugins" || { echo "Failed to create config directory"; exit 1; }
 
-# Copy all of the built plugins to the correct directory
-sudo cp target/release/*.so "${config_dir}/plugins" || { echo "Failed to copy plugins"; exit 1; }
+# Copy the built plugins to the correct directory. The bundled plugins are not part of
+# the workspace until they are ported to version 0.2 of the plugin interface
+if compgen -G "target/release/*.so" > /dev/null; then
+    sudo cp target/release/*.so "${config_dir}/plugins" || { echo "Failed to copy plugins"; exit 1; }
+else
+    echo "No plugins were built, copy the ones you built yourself to ${config_dir}/plugins"
+fi
 
 # Copy the default config file
 sudo cp examples/config.ron "${config_dir}/config.ron" || { echo "Failed to copy config file"; exit 1; }